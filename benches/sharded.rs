@@ -0,0 +1,49 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rust_decimal::Decimal;
+
+use transaction_engine::engine::Engine;
+use transaction_engine::engine_sharded::EngineSharded;
+use transaction_engine::engine_simple::EngineSimple;
+use transaction_engine::transaction::TransactionType;
+use transaction_engine::Transaction;
+
+const TRANSACTIONS: u32 = 1_000_000;
+const CLIENTS: u32 = 10_000;
+
+// Every client gets a transaction per round, and every ten rounds it makes six deposits, two withdrawals,
+// and disputes and resolves the first deposit
+fn transactions() -> Vec<Transaction> {
+    (0..TRANSACTIONS).map(|tx| {
+        let client = (tx % CLIENTS) as u16;
+        let (transaction_type, tx, amount) = match (tx / CLIENTS) % 10 {
+            7 => (TransactionType::Dispute, tx - 7 * CLIENTS, None),
+            8 => (TransactionType::Resolve, tx - 8 * CLIENTS, None),
+            5 | 6 => (TransactionType::Withdrawal, tx, Some(Decimal::new(15, 1))),
+            _ => (TransactionType::Deposit, tx, Some(Decimal::new(25, 1))),
+        };
+
+        Transaction { transaction_type, client, tx, amount }
+    }).collect()
+}
+
+fn analyze(c: &mut Criterion) {
+    let transactions = transactions();
+    let mut group = c.benchmark_group("analyze");
+    group.throughput(Throughput::Elements(u64::from(TRANSACTIONS)));
+    group.sample_size(10);
+
+    group.bench_function("simple", |b| b.iter(|| {
+        EngineSimple::default().analyze(transactions.iter().copied())
+    }));
+
+    for shards in [1, 2, 4, 8] {
+        group.bench_with_input(BenchmarkId::new("sharded", shards), &shards, |b, &shards| b.iter(|| {
+            EngineSharded::new(shards).analyze(transactions.iter().copied())
+        }));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, analyze);
+criterion_main!(benches);
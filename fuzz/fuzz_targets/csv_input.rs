@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+
+use transaction_engine::engine_simple::EngineSimple;
+use transaction_engine::input::analyze_reader;
+use transaction_engine::precision::Precision;
+use transaction_engine::validation::AmountLimits;
+
+// Arbitrary bytes go through the same parsing and analysis as the input files of the binary
+fuzz_target!(|data: &[u8]| {
+    let mut engine = EngineSimple::default()
+        .with_amount_limits(AmountLimits { max_deposit: Decimal::MAX, max_withdrawal: Decimal::MAX })
+        .with_ledger()
+        .with_history();
+    let _ = analyze_reader(&mut engine, data, Precision::default(), &mut 0, |_| {});
+
+    assert_eq!(engine.check_invariants().unwrap(), vec![]);
+    assert_eq!(engine.verify_ledger(), Ok(()));
+});
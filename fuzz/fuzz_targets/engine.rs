@@ -0,0 +1,69 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+
+use transaction_engine::account_repository::AccountRepository;
+use transaction_engine::cached_amounts::CachedAmounts;
+use transaction_engine::engine::Engine;
+use transaction_engine::engine_extended::EngineExtended;
+use transaction_engine::engine_simple::EngineSimple;
+use transaction_engine::seen_transactions::TransactionBitmap;
+use transaction_engine::transaction::TransactionType;
+use transaction_engine::validation::AmountLimits;
+use transaction_engine::Transaction;
+
+#[derive(Debug, Arbitrary)]
+enum FuzzType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+// Few clients and ids, so that transactions often refer to each other
+#[derive(Debug, Arbitrary)]
+struct FuzzTransaction {
+    transaction_type: FuzzType,
+    client: u8,
+    tx: u8,
+    // Any decimal, from its parts
+    amount: Option<(u32, u32, u32, bool, u8)>,
+}
+
+impl From<&FuzzTransaction> for Transaction {
+    fn from(transaction: &FuzzTransaction) -> Self {
+        let transaction_type = match transaction.transaction_type {
+            FuzzType::Deposit => TransactionType::Deposit,
+            FuzzType::Withdrawal => TransactionType::Withdrawal,
+            FuzzType::Dispute => TransactionType::Dispute,
+            FuzzType::Resolve => TransactionType::Resolve,
+            FuzzType::Chargeback => TransactionType::Chargeback,
+        };
+        let amount = transaction.amount.map(|(lo, mid, hi, negative, scale)| Decimal::from_parts(lo, mid, hi, negative, u32::from(scale % 29)));
+
+        Transaction { transaction_type, client: u16::from(transaction.client % 8), tx: u32::from(transaction.tx), amount }
+    }
+}
+
+fuzz_target!(|transactions: Vec<FuzzTransaction>| {
+    let transactions: Vec<Transaction> = transactions.iter().map(Transaction::from).collect();
+    let limits = AmountLimits { max_deposit: Decimal::MAX, max_withdrawal: Decimal::MAX };
+
+    let mut simple = EngineSimple::default().with_amount_limits(limits).with_ledger();
+    assert!(simple.analyze(transactions.iter().copied()).is_ok());
+    assert_eq!(simple.check_invariants().unwrap(), vec![]);
+    assert_eq!(simple.verify_ledger(), Ok(()));
+
+    let mut extended = EngineExtended::new(AccountRepository::new(), CachedAmounts::new(), TransactionBitmap::new())
+        .with_amount_limits(limits)
+        .with_ledger();
+    assert!(extended.analyze(transactions.iter().copied()).is_ok());
+    assert_eq!(extended.check_invariants().unwrap(), vec![]);
+    assert_eq!(extended.verify_ledger(), Ok(()));
+
+    // Every storage backend gives the same accounts
+    assert_eq!(simple.accounts().unwrap(), extended.accounts().unwrap());
+});
@@ -1,71 +1,205 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
 use rust_decimal::Decimal;
-use serde::Serialize;
+use serde::{Deserialize, Serialize, Serializer};
+use serde::ser::SerializeStruct;
+
+use crate::error::ErrorKind;
+use crate::precision::rounded_balance;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum LockPolicy {
+    // Locked accounts reject deposits and withdrawals
+    #[default]
+    RejectTransfers,
+    // Locked accounts also reject disputes, resolutions and chargebacks
+    RejectAll,
+}
+
+// Accounts are always sorted by client id, after any other key
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SortKey {
+    Client,
+    Available,
+    Held,
+    Total,
+    // Unlocked accounts first
+    Locked,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(SortKey::Client),
+            "available" => Ok(SortKey::Available),
+            "held" => Ok(SortKey::Held),
+            "total" => Ok(SortKey::Total),
+            "locked" => Ok(SortKey::Locked),
+            _ => Err(format!("unknown sort key {}", s)),
+        }
+    }
+}
+
+pub fn sort_accounts(accounts: &mut [Account], keys: &[SortKey]) {
+    accounts.sort_by(|a, b| {
+        keys.iter().chain(&[SortKey::Client])
+            .map(|key| match key {
+                SortKey::Client => a.client.cmp(&b.client),
+                SortKey::Available => a.available.cmp(&b.available),
+                SortKey::Held => a.held.cmp(&b.held),
+                SortKey::Total => a.total.cmp(&b.total),
+                SortKey::Locked => a.locked.cmp(&b.locked),
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+}
 
-#[derive(Debug, Serialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, PartialEq, Clone)]
 pub struct Account {
     pub client: u16,
     pub available: Decimal,
     pub held: Decimal,
     pub total: Decimal,
     pub locked: bool,
+    // Why the account has been unlocked, for auditing purposes
+    #[serde(skip)]
+    pub unlock_reasons: Vec<String>,
 }
 
 impl Account {
     pub fn new(client: u16, available: Decimal, held: Decimal, locked: bool) -> Self {
-        Self { client, available, held, total: available + held, locked}
+        Self { client, available, held, total: available + held, locked, unlock_reasons: vec![] }
     }
 
     pub fn empty(client: u16) -> Self {
         Self::new(client, Decimal::from(0), Decimal::from(0), false)
     }
 
-    pub fn deposit(&mut self, amount: Decimal) -> Result<(), String>{
-        self.available += amount;
-        self.total += amount;
-
-        Ok(())
+    pub fn deposit(&mut self, amount: Decimal) -> Result<(), ErrorKind>{
+        self.check_unlocked()?;
+        self.move_funds(amount, Decimal::ZERO)
     }
 
-    pub fn withdraw(&mut self, amount: Decimal) -> Result<(), String>{
+    pub fn withdraw(&mut self, amount: Decimal) -> Result<(), ErrorKind>{
+        self.check_unlocked()?;
         if amount > self.available {
-            return Err(format!("Insufficient available funds"))
+            return Err(ErrorKind::InsufficientAvailableFunds)
         }
-        self.available -= amount;
-        self.total -= amount;
-
-        Ok(())
+        self.move_funds(-amount, Decimal::ZERO)
     }
 
-    pub fn dispute(&mut self, amount: Decimal) -> Result<(), String>{
+    pub fn dispute(&mut self, amount: Decimal) -> Result<(), ErrorKind>{
         if amount > self.available {
-            return Err(format!("Insufficient available funds"))
+            return Err(ErrorKind::InsufficientAvailableFunds)
         }
-        self.available -= amount;
-        self.held += amount;
+        self.move_funds(-amount, amount)
+    }
 
-        Ok(())
+    pub fn resolve(&mut self, amount: Decimal) -> Result<(), ErrorKind>{
+        if amount > self.held {
+            return Err(ErrorKind::InsufficientHeldFunds)
+        }
+        self.move_funds(amount, -amount)
     }
 
-    pub fn resolve(&mut self, amount: Decimal) -> Result<(), String>{
+    pub fn chargeback(&mut self, amount: Decimal) -> Result<(), ErrorKind>{
         if amount > self.held {
-            return Err(format!("Insufficient held funds"))
+            return Err(ErrorKind::InsufficientHeldFunds)
         }
-        self.available += amount;
-        self.held -= amount;
+        self.move_funds(Decimal::ZERO, -amount)?;
+        self.locked = true;
 
         Ok(())
     }
 
-    pub fn chargeback(&mut self, amount: Decimal) -> Result<(), String>{
+    // A disputed withdrawal is credited back as held funds, until the dispute is settled
+    pub fn dispute_withdrawal(&mut self, amount: Decimal) -> Result<(), ErrorKind>{
+        self.move_funds(Decimal::ZERO, amount)
+    }
+
+    pub fn resolve_withdrawal(&mut self, amount: Decimal) -> Result<(), ErrorKind>{
+        if amount > self.held {
+            return Err(ErrorKind::InsufficientHeldFunds)
+        }
+        self.move_funds(Decimal::ZERO, -amount)
+    }
+
+    pub fn chargeback_withdrawal(&mut self, amount: Decimal) -> Result<(), ErrorKind>{
         if amount > self.held {
-            return Err(format!("Insufficient held funds"))
+            return Err(ErrorKind::InsufficientHeldFunds)
         }
-        self.held -= amount;
-        self.total -= amount;
+        self.move_funds(amount, -amount)?;
         self.locked = true;
 
         Ok(())
     }
+
+    pub fn unlock(&mut self, reason: &str) -> Result<(), ErrorKind> {
+        if !self.locked {
+            return Err(ErrorKind::AccountNotLocked);
+        }
+        self.locked = false;
+        self.unlock_reasons.push(reason.to_string());
+
+        Ok(())
+    }
+
+    // Adds the given amounts to available and held funds, and leaves the account untouched if any balance would overflow.
+    // The total is derived from the new balances, since adding a delta to it could round differently past 28 digits.
+    fn move_funds(&mut self, available: Decimal, held: Decimal) -> Result<(), ErrorKind> {
+        let available = self.available.checked_add(available);
+        let held = self.held.checked_add(held);
+        let total = available.zip(held).and_then(|(available, held)| available.checked_add(held));
+
+        match (available, held, total) {
+            (Some(available), Some(held), Some(total)) => {
+                self.available = available;
+                self.held = held;
+                self.total = total;
+
+                Ok(())
+            }
+            _ => Err(ErrorKind::Overflow),
+        }
+    }
+
+    fn check_unlocked(&self) -> Result<(), ErrorKind> {
+        if self.locked {
+            return Err(ErrorKind::AccountLocked);
+        }
+
+        Ok(())
+    }
+
+    // Disputes, resolutions and chargebacks only check the lock when the policy of the engine requires it
+    pub fn check_unlocked_for_disputes(&self, lock_policy: LockPolicy) -> Result<(), ErrorKind> {
+        match lock_policy {
+            LockPolicy::RejectTransfers => Ok(()),
+            LockPolicy::RejectAll => self.check_unlocked(),
+        }
+    }
+}
+
+// Balances are written with a fixed scale, e.g. 2 as 2.0000. The total is the sum of the written available and
+// held funds, so that the output can always be loaded back as opening balances.
+impl Serialize for Account {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let available = rounded_balance(self.available);
+        let held = rounded_balance(self.held);
+        let total = available.checked_add(held).unwrap_or(self.total);
+
+        let mut state = serializer.serialize_struct("Account", 5)?;
+        state.serialize_field("client", &self.client)?;
+        state.serialize_field("available", &available.to_string())?;
+        state.serialize_field("held", &held.to_string())?;
+        state.serialize_field("total", &rounded_balance(total).to_string())?;
+        state.serialize_field("locked", &self.locked)?;
+        state.end()
+    }
 }
 
 #[cfg(test)]
@@ -74,6 +208,45 @@ mod tests {
     use rust_decimal_macros::dec;
     use super::*;
 
+    #[test]
+    fn test_sort_accounts() {
+        let mut accounts = vec![
+            Account::new(3, dec!(1), dec!(0), false),
+            Account::new(1, dec!(2), dec!(0), true),
+            Account::new(2, dec!(2), dec!(0), false),
+        ];
+
+        sort_accounts(&mut accounts, &[]);
+        assert_that!(accounts.iter().map(|account| account.client).collect::<Vec<_>>(), is(equal_to(vec![1, 2, 3])));
+
+        sort_accounts(&mut accounts, &[SortKey::Locked, SortKey::Total]);
+        assert_that!(accounts.iter().map(|account| account.client).collect::<Vec<_>>(), is(equal_to(vec![3, 2, 1])));
+    }
+
+    #[test]
+    fn test_serialize_balances() {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(Account::new(1, dec!(2.0), dec!(3.1234), false)).unwrap();
+
+        assert_that!(String::from_utf8(writer.into_inner().unwrap()).unwrap().as_str(),
+            is(equal_to("client,available,held,total,locked\n1,2.0000,3.1234,5.1234,false\n")));
+    }
+
+    #[test]
+    fn test_serialize_rounded_total() {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(Account::new(2, dec!(0.00015), dec!(0.00015), false)).unwrap();
+
+        assert_that!(String::from_utf8(writer.into_inner().unwrap()).unwrap().as_str(),
+            is(equal_to("client,available,held,total,locked\n2,0.0002,0.0002,0.0004,false\n")));
+    }
+
+    #[test]
+    fn test_parse_sort_key() {
+        assert_that!("total".parse::<SortKey>(), is(equal_to(Ok(SortKey::Total))));
+        assert_that!("name".parse::<SortKey>(), is(equal_to(Err("unknown sort key name".to_string()))));
+    }
+
     #[test]
     fn test_deposit() {
         let mut account = Account::empty(123);
@@ -96,7 +269,7 @@ mod tests {
     #[test]
     fn test_withdraw_insufficient_available_funds() {
         let mut account = Account::empty(123);
-        assert!(account.withdraw(dec!(50)).is_err());
+        assert_that!(account.withdraw(dec!(50)), is(equal_to(Err(ErrorKind::InsufficientAvailableFunds))));
     }
 
     #[test]
@@ -113,7 +286,7 @@ mod tests {
     #[test]
     fn test_dispute_insufficient_available_funds() {
         let mut account = Account::empty(123);
-        assert!(account.dispute(dec!(50)).is_err());
+        assert_that!(account.dispute(dec!(50)), is(equal_to(Err(ErrorKind::InsufficientAvailableFunds))));
     }
 
     #[test]
@@ -131,7 +304,7 @@ mod tests {
     #[test]
     fn test_resolve_insufficient_held_funds() {
         let mut account = Account::empty(123);
-        assert!(account.resolve(dec!(50)).is_err());
+        assert_that!(account.resolve(dec!(50)), is(equal_to(Err(ErrorKind::InsufficientHeldFunds))));
     }
 
     #[test]
@@ -149,7 +322,157 @@ mod tests {
     #[test]
     fn test_chargeback_insufficient_held_funds() {
         let mut account = Account::empty(123);
-        assert!(account.chargeback(dec!(50)).is_err());
+        assert_that!(account.chargeback(dec!(50)), is(equal_to(Err(ErrorKind::InsufficientHeldFunds))));
     }
-}
 
+    #[test]
+    fn test_dispute_withdrawal() {
+        let mut account = Account::empty(123);
+        account.deposit(dec!(100)).unwrap();
+        account.withdraw(dec!(50)).unwrap();
+        account.dispute_withdrawal(dec!(50)).unwrap();
+
+        assert_that!(account.available, is(equal_to(dec!(50))));
+        assert_that!(account.held, is(equal_to(dec!(50))));
+        assert_that!(account.total, is(equal_to(dec!(100))));
+    }
+
+    #[test]
+    fn test_resolve_withdrawal() {
+        let mut account = Account::empty(123);
+        account.deposit(dec!(100)).unwrap();
+        account.withdraw(dec!(50)).unwrap();
+        account.dispute_withdrawal(dec!(50)).unwrap();
+        account.resolve_withdrawal(dec!(50)).unwrap();
+
+        assert_that!(account.available, is(equal_to(dec!(50))));
+        assert_that!(account.held, is(equal_to(dec!(0))));
+        assert_that!(account.total, is(equal_to(dec!(50))));
+    }
+
+    #[test]
+    fn test_chargeback_withdrawal() {
+        let mut account = Account::empty(123);
+        account.deposit(dec!(100)).unwrap();
+        account.withdraw(dec!(50)).unwrap();
+        account.dispute_withdrawal(dec!(50)).unwrap();
+        account.chargeback_withdrawal(dec!(50)).unwrap();
+
+        assert_that!(account.available, is(equal_to(dec!(100))));
+        assert_that!(account.held, is(equal_to(dec!(0))));
+        assert_that!(account.total, is(equal_to(dec!(100))));
+        assert_that!(account.locked, is(true));
+    }
+
+    #[test]
+    fn test_settle_withdrawal_insufficient_held_funds() {
+        let mut account = Account::empty(123);
+
+        assert_that!(account.resolve_withdrawal(dec!(50)), is(equal_to(Err(ErrorKind::InsufficientHeldFunds))));
+        assert_that!(account.chargeback_withdrawal(dec!(50)), is(equal_to(Err(ErrorKind::InsufficientHeldFunds))));
+    }
+
+    #[test]
+    fn test_locked_account_rejects_transfers() {
+        let mut account = Account::new(123, dec!(100), dec!(0), true);
+
+        assert_that!(account.deposit(dec!(50)), is(equal_to(Err(ErrorKind::AccountLocked))));
+        assert_that!(account.withdraw(dec!(50)), is(equal_to(Err(ErrorKind::AccountLocked))));
+        assert_that!(account.available, is(equal_to(dec!(100))));
+    }
+
+    #[test]
+    fn test_locked_account_allows_disputes_by_default() {
+        let mut account = Account::new(123, dec!(100), dec!(0), true);
+
+        account.dispute(dec!(50)).unwrap();
+        account.resolve(dec!(50)).unwrap();
+
+        assert_that!(account.available, is(equal_to(dec!(100))));
+    }
+
+    #[test]
+    fn test_locked_account_rejects_disputes() {
+        let account = Account::new(123, dec!(100), dec!(50), true);
+
+        assert_that!(account.check_unlocked_for_disputes(LockPolicy::RejectTransfers), is(equal_to(Ok(()))));
+        assert_that!(account.check_unlocked_for_disputes(LockPolicy::RejectAll), is(equal_to(Err(ErrorKind::AccountLocked))));
+    }
+
+    #[test]
+    fn test_unlock() {
+        let mut account = Account::new(123, dec!(100), dec!(0), true);
+        account.unlock("Chargeback reverted by the bank").unwrap();
+        account.deposit(dec!(50)).unwrap();
+
+        assert_that!(account.locked, is(false));
+        assert_that!(account.available, is(equal_to(dec!(150))));
+        assert_that!(account.unlock_reasons, is(equal_to(vec!["Chargeback reverted by the bank".to_string()])));
+    }
+
+    #[test]
+    fn test_unlock_unlocked_account() {
+        let mut account = Account::empty(123);
+
+        assert_that!(account.unlock("No reason"), is(equal_to(Err(ErrorKind::AccountNotLocked))));
+    }
+
+    #[test]
+    fn test_deposit_overflow() {
+        let mut account = Account::new(1, dec!(1), dec!(0), false);
+        let expected = account.clone();
+
+        assert_that!(account.deposit(Decimal::MAX), is(equal_to(Err(ErrorKind::Overflow))));
+        assert_that!(account, is(equal_to(expected)));
+    }
+
+    #[test]
+    fn test_deposit_up_to_max() {
+        let mut account = Account::new(1, dec!(1), dec!(0), false);
+
+        account.deposit(Decimal::MAX - dec!(1)).unwrap();
+
+        assert_that!(account, is(equal_to(Account::new(1, Decimal::MAX, dec!(0), false))));
+    }
+
+    #[test]
+    fn test_total_overflow() {
+        let mut account = Account::new(1, Decimal::MAX - dec!(10), dec!(5), false);
+        let expected = account.clone();
+
+        assert_that!(account.deposit(dec!(6)), is(equal_to(Err(ErrorKind::Overflow))));
+        assert_that!(account.dispute_withdrawal(dec!(6)), is(equal_to(Err(ErrorKind::Overflow))));
+        assert_that!(account, is(equal_to(expected)));
+    }
+
+    #[test]
+    fn test_move_funds_at_max() {
+        let mut account = Account::new(1, Decimal::MAX - dec!(10), dec!(10), false);
+
+        account.resolve(dec!(10)).unwrap();
+        account.dispute(Decimal::MAX).unwrap();
+        account.chargeback(Decimal::MAX).unwrap();
+
+        assert_that!(account, is(equal_to(Account::new(1, dec!(0), dec!(0), true))));
+    }
+
+    #[test]
+    fn test_withdraw_down_to_zero() {
+        let mut account = Account::new(1, Decimal::MAX, dec!(0), false);
+
+        account.withdraw(Decimal::MAX).unwrap();
+
+        assert_that!(account, is(equal_to(Account::new(1, dec!(0), dec!(0), false))));
+    }
+
+    #[test]
+    fn test_total_past_28_digits() {
+        let mut account = Account::new(1, dec!(0.1234567890123456789012345678), dec!(0.1234567890123456789012345678), false);
+
+        for amount in [dec!(0.1234567890123456789012345678), dec!(1000000000000000000000000000)] {
+            account.deposit(amount).unwrap();
+
+            assert_that!(account.total, is(equal_to(account.available + account.held)));
+        }
+    }
+}
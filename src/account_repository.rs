@@ -1,23 +1,42 @@
 use std::collections::HashMap;
 use crate::Account;
+use crate::store::{AccountStore, StoreError};
 
 // Simulates a remote/cached repository
 pub struct AccountRepository {
     pub data: HashMap<u16, Account>
 }
 
+impl Default for AccountRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AccountRepository {
     pub fn new() -> Self {
         Self {
             data: HashMap::new()
         }
     }
+}
 
-    pub fn get_or_create(&mut self, client: u16) -> &mut Account {
-        self.data.entry(client).or_insert_with(|| Account::empty(client))
+impl AccountStore for AccountRepository {
+    fn get(&self, client: u16) -> Result<Option<Account>, StoreError> {
+        Ok(self.data.get(&client).cloned())
     }
 
-    pub fn all(&self) -> Vec<Account> {
-        self.data.clone().into_iter().map(|(_, account)| account).collect()
+    fn create(&mut self, account: Account) -> Result<(), StoreError> {
+        self.data.insert(account.client, account);
+        Ok(())
     }
-}
\ No newline at end of file
+
+    fn update(&mut self, account: Account) -> Result<(), StoreError> {
+        self.data.insert(account.client, account);
+        Ok(())
+    }
+
+    fn all(&self) -> Result<Vec<Account>, StoreError> {
+        Ok(self.data.values().cloned().collect())
+    }
+}
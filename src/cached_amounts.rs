@@ -1,8 +1,15 @@
 use std::collections::HashMap;
-use rust_decimal::Decimal;
+use crate::store::{StoreError, TransactionStore};
+use crate::transaction::DisputableTransaction;
 
 pub struct CachedAmounts {
-    data: HashMap<u32, Decimal>,
+    data: HashMap<u32, DisputableTransaction>,
+}
+
+impl Default for CachedAmounts {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CachedAmounts {
@@ -11,19 +18,24 @@ impl CachedAmounts {
             data: HashMap::new(),
         }
     }
+}
+
+impl TransactionStore for CachedAmounts {
+    fn record(&mut self, tx: u32, transaction: DisputableTransaction) -> Result<(), StoreError> {
+        self.data.insert(tx, transaction);
+        Ok(())
+    }
 
-    pub fn add(&mut self, tx: u32, amount: Decimal)  {
-        self.data.insert(tx, amount);
+    fn lookup(&self, tx: u32) -> Result<Option<DisputableTransaction>, StoreError> {
+        Ok(self.data.get(&tx).copied())
     }
 
-    pub fn remove(&mut self, tx: u32)  {
+    fn remove(&mut self, tx: u32) -> Result<(), StoreError> {
         self.data.remove(&tx);
+        Ok(())
     }
 
-    pub fn get(&mut self, tx: u32) -> Option<Decimal> {
-        match self.data.get(&tx) {
-            Some(amount) => Some(amount.clone()),
-            None => None,
-        }
+    fn all(&self) -> Result<Vec<(u32, DisputableTransaction)>, StoreError> {
+        Ok(self.data.iter().map(|(tx, transaction)| (*tx, *transaction)).collect())
     }
-}
\ No newline at end of file
+}
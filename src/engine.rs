@@ -1,5 +1,69 @@
 use crate::{Account, Transaction};
+use crate::account::{sort_accounts, LockPolicy, SortKey};
+use crate::error::{EngineError, ErrorKind};
+use crate::store::StoreError;
+use crate::transaction::{DisputableTransaction, TransactionType};
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum WithdrawalDisputePolicy {
+    // Disputed withdrawals are held until resolved, and given back to the client on chargeback
+    #[default]
+    Reverse,
+    // Only deposits can be disputed
+    Reject,
+}
+
+// Moves the funds of a disputed transaction, in the direction required by whether it was a deposit or a withdrawal
+pub fn apply_dispute_operation(account: &mut Account, operation: TransactionType, disputable: &DisputableTransaction,
+                               lock_policy: LockPolicy, withdrawal_dispute_policy: WithdrawalDisputePolicy) -> Result<(), ErrorKind> {
+    let amount = disputable.amount;
+    account.check_unlocked_for_disputes(lock_policy)?;
+
+    match (operation, disputable.transaction_type) {
+        (TransactionType::Dispute, TransactionType::Withdrawal) => match withdrawal_dispute_policy {
+            WithdrawalDisputePolicy::Reverse => account.dispute_withdrawal(amount),
+            WithdrawalDisputePolicy::Reject => Err(ErrorKind::WithdrawalNotDisputable),
+        },
+        (TransactionType::Dispute, _) => account.dispute(amount),
+        (TransactionType::Resolve, TransactionType::Withdrawal) => account.resolve_withdrawal(amount),
+        (TransactionType::Resolve, _) => account.resolve(amount),
+        (TransactionType::Chargeback, TransactionType::Withdrawal) => account.chargeback_withdrawal(amount),
+        (TransactionType::Chargeback, _) => account.chargeback(amount),
+        (TransactionType::Deposit | TransactionType::Withdrawal, _) => Err(ErrorKind::DuplicateTransaction),
+    }
+}
 
 pub trait Engine {
-    fn analyze(&mut self, transactions: Vec<Transaction>) -> (Vec<Account>, Vec<String>);
+    fn analyze_transaction(&mut self, transaction: Transaction) -> Result<(), EngineError>;
+
+    // Sorted by client id
+    fn accounts(&self) -> Result<Vec<Account>, StoreError>;
+
+    fn sorted_accounts(&self, keys: &[SortKey]) -> Result<Vec<Account>, StoreError> {
+        let mut accounts = self.accounts()?;
+        sort_accounts(&mut accounts, keys);
+
+        Ok(accounts)
+    }
+
+    // Unlocking is an explicit operation, so the reason is kept with the account for auditing
+    fn unlock(&mut self, client: u16, reason: &str) -> Result<(), ErrorKind>;
+
+    // Transactions are consumed one at a time, so memory only grows with accounts and disputable transactions
+    fn analyze_with<I, F>(&mut self, transactions: I, mut on_error: F)
+        where I: IntoIterator<Item=Transaction>, F: FnMut(EngineError) {
+        for transaction in transactions {
+            if let Err(err) = self.analyze_transaction(transaction) {
+                on_error(err);
+            }
+        }
+    }
+
+    fn analyze<I>(&mut self, transactions: I) -> Result<(Vec<Account>, Vec<EngineError>), StoreError>
+        where I: IntoIterator<Item=Transaction> {
+        let mut errors = vec![];
+        self.analyze_with(transactions, |err| errors.push(err));
+
+        Ok((self.accounts()?, errors))
+    }
 }
@@ -1,105 +1,1375 @@
-use crate::account_repository::AccountRepository;
+use std::collections::HashMap;
+use std::io;
+
+use rust_decimal::Decimal;
+
 use crate::{Account, Transaction};
-use crate::cached_amounts::CachedAmounts;
-use crate::engine::Engine;
-
-// This extended version shows how we could cache data for accounts or for transactions
-pub struct EngineExtended {
-    account_repository: AccountRepository,
-    applied_transactions: CachedAmounts,
-    disputed_transactions: CachedAmounts,
+use crate::account::{sort_accounts, LockPolicy};
+use crate::engine::{apply_dispute_operation, Engine, WithdrawalDisputePolicy};
+use crate::error::{EngineError, ErrorKind};
+use crate::ledger::{Ledger, LedgerEntry, LedgerError};
+use crate::history::{History, HistoryEntry};
+use crate::invariants::{check_accounts, InvariantViolation};
+#[cfg(debug_assertions)]
+use crate::invariants::check_account;
+use crate::journal::{BalanceDelta, Journal, JournalEntry, JournalError};
+use crate::opening_balances::{load_opening_balances, OpeningBalancesError};
+use crate::seen_transactions::SeenTransactions;
+use crate::snapshot::{read_snapshot, write_snapshot, SnapshotError};
+use crate::store::{AccountStore, StoreError, TransactionStore};
+use crate::transaction::{DisputableTransaction, TransactionType};
+use crate::transaction_state::TransactionState;
+use crate::validation::AmountLimits;
+
+// The same engine runs on top of any storage, e.g. in memory, on a remote/cached repository or on a database
+pub struct EngineExtended<A: AccountStore, T: TransactionStore, S: SeenTransactions> {
+    lock_policy: LockPolicy,
+    withdrawal_dispute_policy: WithdrawalDisputePolicy,
+    amount_limits: AmountLimits,
+    accounts: A,
+    transactions: T,
+    seen_transactions: S,
+    journal: Option<Journal>,
+    ledger: Option<Ledger>,
+    history: Option<History>,
+    // Sum of the amounts of the disputed transactions of every client, which must match their held funds
+    open_disputes: HashMap<u16, Decimal>,
+}
+
+impl<A, T, S> Default for EngineExtended<A, T, S>
+    where A: AccountStore + Default, T: TransactionStore + Default, S: SeenTransactions + Default {
+    fn default() -> Self {
+        Self::new(A::default(), T::default(), S::default())
+    }
 }
 
-impl EngineExtended {
-    pub fn new(account_repository: AccountRepository, applied_transactions: CachedAmounts, disputed_transactions: CachedAmounts) -> Self {
+impl<A: AccountStore, T: TransactionStore, S: SeenTransactions> EngineExtended<A, T, S> {
+    pub fn new(accounts: A, transactions: T, seen_transactions: S) -> Self {
         Self {
-            account_repository,
-            applied_transactions,
-            disputed_transactions
+            lock_policy: LockPolicy::default(),
+            withdrawal_dispute_policy: WithdrawalDisputePolicy::default(),
+            amount_limits: AmountLimits::default(),
+            accounts,
+            transactions,
+            seen_transactions,
+            journal: None,
+            ledger: None,
+            history: None,
+            open_disputes: HashMap::new(),
         }
     }
 
-    pub fn analyze_transaction(&mut self, transaction: Transaction) -> Result<(), String> {
-        let account = self.account_repository.get_or_create(transaction.client);
+    pub fn with_lock_policy(mut self, lock_policy: LockPolicy) -> Self {
+        self.lock_policy = lock_policy;
+        self
+    }
+
+    pub fn with_withdrawal_dispute_policy(mut self, withdrawal_dispute_policy: WithdrawalDisputePolicy) -> Self {
+        self.withdrawal_dispute_policy = withdrawal_dispute_policy;
+        self
+    }
+
+    pub fn with_amount_limits(mut self, amount_limits: AmountLimits) -> Self {
+        self.amount_limits = amount_limits;
+        self
+    }
+
+    // Saves accounts, applied transactions with their dispute state and seen ids
+    pub fn save_snapshot<W: io::Write>(&self, writer: W) -> Result<(), SnapshotError> {
+        write_snapshot(writer, &self.accounts, &self.transactions, &self.seen_transactions, &self.open_disputes)
+    }
+
+    // Meant to be called on a new engine, before analyzing any transaction
+    pub fn restore_snapshot<R: io::Read>(&mut self, reader: R) -> Result<(), SnapshotError> {
+        self.open_disputes = read_snapshot(reader, &mut self.accounts, &mut self.transactions, &mut self.seen_transactions)?;
+        self.open_ledger()?;
+
+        Ok(())
+    }
+
+    // Starts from the balances of a previous run, in the same format as the output
+    pub fn load_accounts<R: io::Read>(&mut self, reader: R) -> Result<(), OpeningBalancesError> {
+        load_opening_balances(reader, &mut self.accounts)?;
+        self.open_ledger()?;
+
+        // Opening held funds belong to disputes that were opened before
+        self.open_disputes = self.accounts.all()?.iter().map(|account| (account.client, account.held)).collect();
+
+        Ok(())
+    }
+
+    // Records every balance change in a double-entry ledger, starting from the current balances.
+    // Only the balances are kept, see `with_ledger_writer` to keep the entries as well.
+    pub fn with_ledger(mut self) -> Self {
+        self.ledger = Some(Ledger::new());
+        self
+    }
 
-        match transaction.transaction_type.as_str() {
-            "deposit" => {
-                account.deposit(transaction.amount).unwrap();
-                self.applied_transactions.add(transaction.tx, transaction.amount);
+    // Like `with_ledger`, writing every entry out instead of keeping it in memory
+    pub fn with_ledger_writer<W: io::Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.ledger = Some(Ledger::writing_to(writer));
+        self
+    }
+
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
+
+    // Checks the balances of every account against the ledger, if any
+    pub fn verify_ledger(&self) -> Result<(), LedgerError> {
+        match &self.ledger {
+            Some(ledger) => self.accounts.all()?.iter().try_for_each(|account| ledger.verify(account)),
+            None => Ok(()),
+        }
+    }
+
+    // Checks every account against the ledger and writes out the buffered entries, once all the input
+    // has been analyzed
+    pub fn complete_ledger(&mut self) -> Result<(), LedgerError> {
+        self.verify_ledger()?;
+
+        match &mut self.ledger {
+            Some(ledger) => Ok(ledger.flush()?),
+            None => Ok(()),
+        }
+    }
+
+    // Marks a transaction id as used, e.g. by a transaction analyzed on another engine
+    pub fn reserve_transaction(&mut self, tx: u32) -> Result<(), StoreError> {
+        self.seen_transactions.insert(tx).map(|_| ())
+    }
+
+    // An applied transaction, e.g. to tell which client it belongs to when it is disputed on another engine
+    pub fn lookup_transaction(&self, tx: u32) -> Result<Option<DisputableTransaction>, StoreError> {
+        self.transactions.lookup(tx)
+    }
+
+    // Checks every account: totals, held funds and open disputes must be consistent
+    pub fn check_invariants(&self) -> Result<Vec<InvariantViolation>, StoreError> {
+        check_accounts(&self.accounts, &self.open_disputes)
+    }
+
+    // Only in debug builds, since it costs an extra read of the account after every transaction.
+    // The account must also match the ledger, if any.
+    #[cfg(debug_assertions)]
+    fn assert_invariants(&self, client: u16) {
+        if let Ok(Some(account)) = self.accounts.get(client) {
+            let open_disputes = self.open_disputes.get(&client).copied().unwrap_or_default();
+
+            // A broken invariant is a bug in the engine, so debug builds stop right away
+            #[allow(clippy::panic)]
+            if let Err(violation) = check_account(&account, open_disputes) {
+                panic!("{}", violation);
             }
-            "withdrawal" => {
-                match account.withdraw(transaction.amount) {
-                    Ok(_) => self.applied_transactions.add(transaction.tx, transaction.amount),
-                    Err(err) => {
-                        return Err(format!("Error when handling transaction \"{}\": {}", transaction.tx, err));
-                    }
-                };
+
+            #[allow(clippy::panic)]
+            if let Some(Err(err)) = self.ledger.as_ref().map(|ledger| ledger.verify(&account)) {
+                panic!("{}", err);
             }
-            "dispute" => {
-                let disputable = match self.applied_transactions.get(transaction.tx) {
-                    Some(disputable) => disputable,
-                    None => {
-                        return Err(format!("Could not find applied transaction \"{}\" to dispute", transaction.tx));
-                    }
-                };
+        }
+    }
 
-                match account.dispute(disputable) {
-                    Ok(_) => self.disputed_transactions.add(transaction.tx, disputable),
-                    Err(err) => {
-                        return Err(format!("Could not dispute transaction \"{}\": {}", transaction.tx, err));
-                    }
-                };
+    // Keeps every analyzed transaction, including rejected ones, to be queried by client
+    pub fn with_history(mut self) -> Self {
+        self.history = Some(History::new());
+        self
+    }
+
+    // Same as `with_history`, but only keeps the transactions of the given client
+    pub fn with_history_for(mut self, client: u16) -> Self {
+        self.history = Some(History::of_client(client));
+        self
+    }
+
+    // Empty when the history is not kept
+    pub fn history(&self, client: u16) -> &[HistoryEntry] {
+        self.history.as_ref().map_or(&[], |history| history.of(client))
+    }
+
+    fn record_history(&mut self, transaction: &Transaction, result: &Result<(), EngineError>) -> Result<(), StoreError> {
+        if !self.history.as_ref().is_some_and(|history| history.keeps(transaction.client)) {
+            return Ok(());
+        }
+
+        let account = match self.accounts.get(transaction.client)? {
+            Some(account) => account,
+            None => Account::empty(transaction.client),
+        };
+        let error = result.as_ref().err().map(|err| err.kind);
+
+        if let Some(history) = &mut self.history {
+            history.record(HistoryEntry::new(transaction, &account, error));
+        }
+
+        Ok(())
+    }
+
+    // Loaded balances become the opening balances of the ledger
+    fn open_ledger(&mut self) -> Result<(), LedgerError> {
+        if let Some(ledger) = &mut self.ledger {
+            ledger.open(&self.accounts.all()?)?;
+        }
+
+        Ok(())
+    }
+
+    fn post(&mut self, entry: LedgerEntry) -> Result<(), ErrorKind> {
+        match &mut self.ledger {
+            Some(ledger) => ledger.post(entry),
+            None => Ok(()),
+        }
+    }
+
+    // Every analyzed transaction is journaled: accepted ones before the stores are updated
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    // Replays the journal on a new engine (or on the same starting state as when it was written),
+    // and returns how many input transactions have already been analyzed and should be skipped
+    pub fn recover(&mut self) -> Result<usize, JournalError> {
+        let mut journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return Ok(0),
+        };
+        let entries = journal.entries();
+        let result = entries.and_then(|entries| self.replay(&entries).map(|_| entries.len()));
+        self.journal = Some(journal);
+
+        result
+    }
+
+    // Empties the journal once all the input has been analyzed, so that the next run doesn't replay it
+    pub fn complete_journal(&mut self) -> Result<(), JournalError> {
+        match &mut self.journal {
+            Some(journal) => Ok(journal.clear()?),
+            None => Ok(()),
+        }
+    }
+
+    fn replay(&mut self, entries: &[JournalEntry]) -> Result<(), JournalError> {
+        for (position, entry) in entries.iter().enumerate() {
+            let delta = match self.apply(&entry.transaction) {
+                Ok(delta) => Some(delta),
+                Err(EngineError { kind: ErrorKind::Store(err), .. }) => return Err(JournalError::Store(err)),
+                Err(_) => None,
+            };
+
+            if delta != entry.delta {
+                return Err(JournalError::Diverged(position));
             }
-            "resolve" => {
-                let resolvable = match self.disputed_transactions.get(transaction.tx) {
-                    Some(amount) => amount,
-                    None => {
-                        return Err(format!("Could not find disputed transaction \"{}\" to resolve", transaction.tx));
-                    }
-                };
+        }
 
-                match account.resolve(resolvable) {
-                    Ok(_) => self.disputed_transactions.remove(transaction.tx),
-                    Err(err) => {
-                        return Err(format!("Could not resolve disputed transaction \"{}\": {}", transaction.tx, err));
-                    }
-                };
+        Ok(())
+    }
+
+    fn write_ahead(&mut self, transaction: &Transaction, delta: Option<BalanceDelta>) -> Result<(), StoreError> {
+        match &mut self.journal {
+            Some(journal) => journal.append(&JournalEntry { transaction: *transaction, delta }),
+            None => Ok(()),
+        }
+    }
+
+    // Returns how the balances of the account changed.
+    // Every transaction creates the account of its client and uses up its id (see `Transaction::uses_id`)
+    // before any other check, so a rejection only depends on what comes after.
+    fn apply(&mut self, transaction: &Transaction) -> Result<BalanceDelta, EngineError> {
+        let store_error = |err| EngineError::new(ErrorKind::Store(err), transaction, transaction.amount);
+        let mut account = self.get_or_create_account(transaction.client).map_err(store_error)?;
+        let before = account.clone();
+
+        if transaction.uses_id() && !self.seen_transactions.insert(transaction.tx).map_err(store_error)? {
+            return Err(EngineError::new(ErrorKind::DuplicateTransaction, transaction, transaction.amount));
+        }
+
+        self.amount_limits.validate(transaction)?;
+
+        let delta = match transaction.transaction_type {
+            TransactionType::Deposit => {
+                let amount = transaction.required_amount()?;
+
+                if let Err(err) = account.deposit(amount) {
+                    return Err(EngineError::new(err, transaction, Some(amount)));
+                }
+
+                let entry = LedgerEntry::for_operation(transaction.tx, transaction.client, transaction.transaction_type, transaction.transaction_type, amount);
+                self.commit(transaction, &before, account, None, DisputableTransaction::new(transaction, amount), entry)?
+            }
+            TransactionType::Withdrawal => {
+                let amount = transaction.required_amount()?;
+
+                if let Err(err) = account.withdraw(amount) {
+                    return Err(EngineError::new(err, transaction, Some(amount)));
+                }
+
+                let entry = LedgerEntry::for_operation(transaction.tx, transaction.client, transaction.transaction_type, transaction.transaction_type, amount);
+                self.commit(transaction, &before, account, None, DisputableTransaction::new(transaction, amount), entry)?
             }
-            "chargeback" => {
-                let back_chargeable = match self.disputed_transactions.get(transaction.tx) {
-                    Some(amount) => amount,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                let mut disputable = match self.transactions.lookup(transaction.tx).map_err(store_error)? {
+                    Some(disputable) => disputable,
                     None => {
-                        return Err(format!("Could not find disputed transaction \"{}\" to charge", transaction.tx));
+                        return Err(EngineError::new(ErrorKind::UnknownTransaction, transaction, None));
                     }
                 };
 
-                match account.chargeback(back_chargeable) {
-                    Ok(_) => self.disputed_transactions.remove(transaction.tx),
+                if disputable.client != transaction.client {
+                    return Err(EngineError::new(ErrorKind::ClientMismatch, transaction, Some(disputable.amount)));
+                }
+
+                let state = match disputable.state.transition(transaction.transaction_type) {
+                    Ok(state) => state,
                     Err(err) => {
-                        return Err(format!("Could not charge back disputed transaction \"{}\": {}", transaction.tx, err));
+                        return Err(EngineError::new(err, transaction, Some(disputable.amount)));
                     }
                 };
+
+                if let Err(err) = apply_dispute_operation(&mut account, transaction.transaction_type, &disputable, self.lock_policy, self.withdrawal_dispute_policy) {
+                    return Err(EngineError::new(err, transaction, Some(disputable.amount)));
+                }
+
+                let open_disputes = self.open_disputes.get(&transaction.client).copied().unwrap_or_default();
+                let open_disputes = match state {
+                    TransactionState::Disputed => open_disputes.checked_add(disputable.amount),
+                    _ => open_disputes.checked_sub(disputable.amount),
+                }.ok_or_else(|| EngineError::new(ErrorKind::Overflow, transaction, Some(disputable.amount)))?;
+
+                let previous = disputable;
+                disputable.state = state;
+                let entry = LedgerEntry::for_operation(transaction.tx, transaction.client, transaction.transaction_type, previous.transaction_type, previous.amount);
+                let delta = self.commit(transaction, &before, account, Some(previous), disputable, entry)?;
+                self.open_disputes.insert(transaction.client, open_disputes);
+                delta
             }
-            t => {
-                return Err(format!("Unhandled transaction type: \"{}\"", t));
-            },
         };
 
+        Ok(delta)
+    }
+
+    // Writes an accepted transaction, so that a failing store leaves nothing behind: the journal entry is dropped,
+    // and the ledger is only posted once both stores succeeded (after checking that it can be)
+    fn commit(&mut self, transaction: &Transaction, before: &Account, account: Account, previous: Option<DisputableTransaction>,
+              disputable: DisputableTransaction, entry: LedgerEntry) -> Result<BalanceDelta, EngineError> {
+        let store_error = |err| EngineError::new(ErrorKind::Store(err), transaction, transaction.amount);
+        let ledger_error = |err| EngineError::new(err, transaction, Some(entry.amount));
+
+        if let Some(ledger) = &self.ledger {
+            ledger.check(&entry).map_err(ledger_error)?;
+        }
+
+        let journal_error = |err| EngineError::new(ErrorKind::Journal(err), transaction, transaction.amount);
+
+        let delta = BalanceDelta::between(before, &account);
+        self.write_ahead(transaction, Some(delta)).map_err(journal_error)?;
+
+        if let Err(err) = self.store(transaction.tx, account, previous, disputable) {
+            if let Some(journal) = &mut self.journal {
+                journal.rollback().map_err(journal_error)?;
+            }
+
+            return Err(store_error(err));
+        }
+
+        self.post(entry).map_err(ledger_error)?;
+
+        Ok(delta)
+    }
+
+    // The transaction is recorded first, and put back as it was if the account cannot be updated
+    fn store(&mut self, tx: u32, account: Account, previous: Option<DisputableTransaction>, disputable: DisputableTransaction) -> Result<(), StoreError> {
+        self.transactions.record(tx, disputable)?;
+
+        if let Err(err) = self.accounts.update(account) {
+            // The original failure is the one worth reporting
+            let _ = match previous {
+                Some(previous) => self.transactions.record(tx, previous),
+                None => self.transactions.remove(tx),
+            };
+
+            return Err(err);
+        }
+
         Ok(())
     }
+
+    fn get_or_create_account(&mut self, client: u16) -> Result<Account, StoreError> {
+        if let Some(account) = self.accounts.get(client)? {
+            return Ok(account);
+        }
+
+        let account = Account::empty(client);
+        self.accounts.create(account.clone())?;
+
+        Ok(account)
+    }
 }
 
-impl Engine for EngineExtended {
-    fn analyze(&mut self, transactions: Vec<Transaction>) -> (Vec<Account>, Vec<String>) {
-        let mut errors = vec![];
+impl<A: AccountStore, T: TransactionStore, S: SeenTransactions> Engine for EngineExtended<A, T, S> {
+    fn analyze_transaction(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let store_error = |err| EngineError::new(ErrorKind::Store(err), &transaction, transaction.amount);
+        let result = self.apply(&transaction).map(|_| ());
+
+        // Rejections are journaled after the fact, since they don't change the stores. Failing to journal one is
+        // fatal, like for accepted transactions, since the position in the input would be lost.
+        match &result {
+            Err(EngineError { kind: ErrorKind::Store(_) | ErrorKind::Journal(_) | ErrorKind::Ledger(_), .. }) | Ok(_) => {}
+            Err(_) => {
+                self.write_ahead(&transaction, None)
+                    .map_err(|err| EngineError::new(ErrorKind::Journal(err), &transaction, transaction.amount))?;
+            }
+        }
+
+        self.record_history(&transaction, &result).map_err(store_error)?;
+
+        #[cfg(debug_assertions)]
+        self.assert_invariants(transaction.client);
+
+        result
+    }
+
+    fn accounts(&self) -> Result<Vec<Account>, StoreError> {
+        let mut accounts = self.accounts.all()?;
+        sort_accounts(&mut accounts, &[]);
+
+        Ok(accounts)
+    }
+
+    fn unlock(&mut self, client: u16, reason: &str) -> Result<(), ErrorKind> {
+        let mut account = match self.accounts.get(client).map_err(ErrorKind::Store)? {
+            Some(account) => account,
+            None => return Err(ErrorKind::UnknownClient),
+        };
+
+        account.unlock(reason)?;
+        self.accounts.update(account).map_err(ErrorKind::Store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use hamcrest::*;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::{Account, Transaction};
+    use crate::account_repository::AccountRepository;
+    use crate::cached_amounts::CachedAmounts;
+    use crate::engine_simple::EngineSimple;
+    use crate::error::ErrorKind;
+    use crate::file_transaction_store::tests::{temp_path, temp_store};
+    use crate::ledger::LedgerAccount;
+    use crate::seen_transactions::TransactionBitmap;
+    use crate::transaction::TransactionType::*;
+
+    use super::*;
+
+    const CLIENT_ID: u16 = 123;
+    const OTHER_CLIENT_ID: u16 = 456;
+
+    // Runs the same suite against every storage backend
+    macro_rules! engine_tests {
+        ($backend:ident, $engine:expr) => {
+            mod $backend {
+                use super::*;
+
+                #[test]
+                fn test_no_transactions() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![]).unwrap();
+                    assert_that!(accounts, is(equal_to(vec![])));
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_deposit() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(3.1234))),
+                    ]).unwrap();
+
+                    assert_that!(accounts, is(equal_to(vec![
+                        Account::new(CLIENT_ID, dec!(3.1234), dec!(0.0), false),
+                    ])));
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_deposit_without_amount() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 2, None),
+                    ]).unwrap();
+
+                    assert_that!(accounts, is(equal_to(vec![
+                        Account::new(CLIENT_ID, dec!(0.0), dec!(0.0), false),
+                    ])));
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::MissingAmount)));
+                }
+
+                #[test]
+                fn test_reject_invalid_amounts() {
+                    let mut engine = $engine.with_amount_limits(AmountLimits { max_deposit: dec!(1000.0), max_withdrawal: dec!(10.0) });
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(-100.0))),
+                        Transaction::new(Withdrawal, CLIENT_ID, 3, Some(dec!(0.0))),
+                        Transaction::new(Withdrawal, CLIENT_ID, 4, Some(dec!(50.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 5, Some(dec!(5000.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 6, Some(dec!(1.0))),
+                        Transaction::new(Deposit, OTHER_CLIENT_ID, 7, Some(dec!(0.0))),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(101.0), dec!(0.0), dec!(101.0), false);
+                    assert_that!(accounts[1].clone(), is(equal_to(Account::empty(OTHER_CLIENT_ID))));
+                    assert_eq!(errors.len(), 5);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::NonPositiveAmount)));
+                    assert_that!(errors[1].kind, is(equal_to(ErrorKind::NonPositiveAmount)));
+                    assert_that!(errors[2].kind, is(equal_to(ErrorKind::AmountTooLarge)));
+                    assert_that!(errors[3].kind, is(equal_to(ErrorKind::AmountTooLarge)));
+                    assert_that!(errors[4].kind, is(equal_to(ErrorKind::NonPositiveAmount)));
+                }
+
+                #[test]
+                fn test_invalid_amounts_use_up_ids() {
+                    let mut engine = $engine.with_amount_limits(AmountLimits { max_deposit: dec!(1000.0), max_withdrawal: dec!(10.0) });
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(5000.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 2, None),
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(100.0))),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_that!(errors.iter().map(|err| err.kind).collect::<Vec<_>>(), is(equal_to(vec![
+                        ErrorKind::AmountTooLarge, ErrorKind::DuplicateTransaction, ErrorKind::MissingAmount,
+                    ])));
+                }
+
+                #[test]
+                fn test_withdrawal() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(3.1234))),
+                        Transaction::new(Withdrawal, CLIENT_ID, 3, Some(dec!(3.1234))),
+                    ]).unwrap();
+
+                    assert_that!(accounts, is(equal_to(vec![
+                        Account::new(CLIENT_ID, dec!(0.0), dec!(0.0), false),
+                    ])));
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_reject_duplicate_transactions() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Withdrawal, CLIENT_ID, 1, Some(dec!(50.0))),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 2);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::DuplicateTransaction)));
+                    assert_that!(errors[1].kind, is(equal_to(ErrorKind::DuplicateTransaction)));
+                }
+
+                #[test]
+                fn test_reject_duplicate_transactions_at_boundary() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, u32::MAX, Some(dec!(100.0))),
+                        Transaction::new(Deposit, CLIENT_ID, u32::MAX, Some(dec!(100.0))),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::DuplicateTransaction)));
+                }
+
+                #[test]
+                fn test_withdrawal_from_insufficient_funds() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Withdrawal, CLIENT_ID, 2, Some(dec!(3.1234)))
+                    ]).unwrap();
+
+                    assert_that!(accounts, is(equal_to(vec![Account::new(CLIENT_ID, dec!(0.0), dec!(0.0), false)])));
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::InsufficientAvailableFunds)));
+                }
+
+                #[test]
+                fn test_dispute() {
+                    let mut engine = $engine;
 
-        for transaction in transactions {
-            match self.analyze_transaction(transaction) {
-                Ok(_) => {},
-                Err(err) => errors.push(err),
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 1, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(0.0), dec!(100.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_cannot_dispute_twice() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(100.0), dec!(200.0), false);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::AlreadyDisputed)));
+                }
+
+                #[test]
+                fn test_ignore_dispute_for_unknown_transaction() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 999, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::UnknownTransaction)));
+                }
+
+                #[test]
+                fn test_resolve() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 1, None),
+                        Transaction::new(Resolve, CLIENT_ID, 1, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_cannot_resolve_twice() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                        Transaction::new(Resolve, CLIENT_ID, 2, None),
+                        Transaction::new(Resolve, CLIENT_ID, 2, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(200.0), dec!(0.0), dec!(200.0), false);
+                    assert_eq!(errors.len(), 1);
+                }
+
+                #[test]
+                fn test_dispute_again_after_resolve() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 1, None),
+                        Transaction::new(Resolve, CLIENT_ID, 1, None),
+                        Transaction::new(Dispute, CLIENT_ID, 1, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(0.0), dec!(100.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_ignore_resolve_for_unknown_transaction() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Resolve, CLIENT_ID, 999, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 1);
+                }
+
+                #[test]
+                fn test_ignore_undisputed_resolve() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Resolve, CLIENT_ID, 1, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::NotDisputed)));
+                }
+
+                #[test]
+                fn test_chargeback() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 1, None),
+                        Transaction::new(Chargeback, CLIENT_ID, 1, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(0.0), dec!(0.0), dec!(0.0), true);
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_cannot_chargeback_twice() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                        Transaction::new(Chargeback, CLIENT_ID, 2, None),
+                        Transaction::new(Chargeback, CLIENT_ID, 2, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), true);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::AlreadyChargedBack)));
+                }
+
+                #[test]
+                fn test_cannot_dispute_after_chargeback() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                        Transaction::new(Chargeback, CLIENT_ID, 2, None),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), true);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::AlreadyChargedBack)));
+                }
+
+                #[test]
+                fn test_ignore_chargeback_for_unknown_transaction() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Chargeback, CLIENT_ID, 999, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 1);
+                }
+
+                #[test]
+                fn test_ignore_undisputed_chargeback() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Chargeback, CLIENT_ID, 1, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::NotDisputed)));
+                }
+
+                #[test]
+                fn test_dispute_withdrawal() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Withdrawal, CLIENT_ID, 2, Some(dec!(40.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(60.0), dec!(40.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_resolve_disputed_withdrawal() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Withdrawal, CLIENT_ID, 2, Some(dec!(40.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                        Transaction::new(Resolve, CLIENT_ID, 2, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(60.0), dec!(0.0), dec!(60.0), false);
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_chargeback_disputed_withdrawal() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Withdrawal, CLIENT_ID, 2, Some(dec!(40.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                        Transaction::new(Chargeback, CLIENT_ID, 2, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), true);
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_dispute_withdrawal_with_spent_funds() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Withdrawal, CLIENT_ID, 2, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(0.0), dec!(100.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_reject_withdrawal_dispute() {
+                    let mut engine = $engine.with_withdrawal_dispute_policy(WithdrawalDisputePolicy::Reject);
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Withdrawal, CLIENT_ID, 2, Some(dec!(40.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(60.0), dec!(0.0), dec!(60.0), false);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::WithdrawalNotDisputable)));
+                }
+
+                #[test]
+                fn test_cannot_dispute_another_client_transaction() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Dispute, OTHER_CLIENT_ID, 1, None),
+                    ]).unwrap();
+
+                    assert_account(find_account(&accounts, CLIENT_ID), dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_account(find_account(&accounts, OTHER_CLIENT_ID), dec!(0.0), dec!(0.0), dec!(0.0), false);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::ClientMismatch)));
+                }
+
+                #[test]
+                fn test_cannot_resolve_another_client_transaction() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 1, None),
+                        Transaction::new(Resolve, OTHER_CLIENT_ID, 1, None),
+                    ]).unwrap();
+
+                    assert_account(find_account(&accounts, CLIENT_ID), dec!(0.0), dec!(100.0), dec!(100.0), false);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::ClientMismatch)));
+                }
+
+                #[test]
+                fn test_cannot_chargeback_another_client_transaction() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 1, None),
+                        Transaction::new(Chargeback, OTHER_CLIENT_ID, 1, None),
+                    ]).unwrap();
+
+                    assert_account(find_account(&accounts, CLIENT_ID), dec!(0.0), dec!(100.0), dec!(100.0), false);
+                    assert_account(find_account(&accounts, OTHER_CLIENT_ID), dec!(0.0), dec!(0.0), dec!(0.0), false);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::ClientMismatch)));
+                }
+
+                #[test]
+                fn test_locked_account_rejects_transfers() {
+                    let mut engine = $engine;
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                        Transaction::new(Chargeback, CLIENT_ID, 2, None),
+                        Transaction::new(Deposit, CLIENT_ID, 3, Some(dec!(100.0))),
+                        Transaction::new(Withdrawal, CLIENT_ID, 4, Some(dec!(50.0))),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), true);
+                    assert_eq!(errors.len(), 2);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::AccountLocked)));
+                    assert_that!(errors[1].kind, is(equal_to(ErrorKind::AccountLocked)));
+                }
+
+                #[test]
+                fn test_locked_account_rejects_disputes() {
+                    let mut engine = $engine.with_lock_policy(LockPolicy::RejectAll);
+
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 2, None),
+                        Transaction::new(Chargeback, CLIENT_ID, 2, None),
+                        Transaction::new(Dispute, CLIENT_ID, 1, None),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), true);
+                    assert_eq!(errors.len(), 1);
+                    assert_that!(errors[0].kind, is(equal_to(ErrorKind::AccountLocked)));
+                }
+
+                #[test]
+                fn test_unlock() {
+                    let mut engine = $engine;
+
+                    engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+                        Transaction::new(Dispute, CLIENT_ID, 1, None),
+                        Transaction::new(Chargeback, CLIENT_ID, 1, None),
+                    ]).unwrap();
+                    engine.unlock(CLIENT_ID, "Chargeback reverted by the bank").unwrap();
+                    let (accounts, errors) = engine.analyze(vec![
+                        Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(100.0))),
+                    ]).unwrap();
+
+                    assert_account(&accounts[0], dec!(100.0), dec!(0.0), dec!(100.0), false);
+                    assert_that!(accounts[0].unlock_reasons.len(), is(equal_to(1)));
+                    assert_eq!(errors.len(), 0);
+                }
+
+                #[test]
+                fn test_unlock_unknown_client() {
+                    let mut engine = $engine;
+
+                    assert_that!(engine.unlock(CLIENT_ID, "No reason"), is(equal_to(Err(ErrorKind::UnknownClient))));
+                }
             }
+        };
+    }
+
+    engine_tests!(simple, EngineSimple::default());
+    engine_tests!(extended, EngineExtended::new(AccountRepository::new(), CachedAmounts::new(), TransactionBitmap::new()));
+    engine_tests!(mixed, EngineExtended::new(HashMap::new(), CachedAmounts::new(), HashSet::new()));
+    engine_tests!(on_disk, EngineExtended::new(AccountRepository::new(), temp_store(), TransactionBitmap::new()));
+
+    // Random sequences of transactions, on a few clients and ids so that disputes often refer to existing transactions
+    mod properties {
+        use proptest::prelude::*;
+
+        use crate::transaction::tests::transaction;
+
+        use super::*;
+
+        const CLIENTS: u16 = 4;
+
+        proptest! {
+            #[test]
+            fn test_same_results_on_every_backend(transactions in proptest::collection::vec(transaction(CLIENTS, 30), 0..200)) {
+                let expected = EngineSimple::default().analyze(transactions.clone()).unwrap();
+
+                let extended = EngineExtended::new(AccountRepository::new(), CachedAmounts::new(), TransactionBitmap::new()).analyze(transactions.clone());
+                prop_assert_eq!(extended.unwrap(), expected.clone());
+
+                let on_disk = EngineExtended::new(HashMap::new(), temp_store(), HashSet::new()).analyze(transactions);
+                prop_assert_eq!(on_disk.unwrap(), expected);
+            }
+
+            #[test]
+            fn test_totals_match_accepted_transactions(transactions in proptest::collection::vec(transaction(CLIENTS, 30), 0..200)) {
+                let mut engine = EngineSimple::default()
+                    .with_withdrawal_dispute_policy(WithdrawalDisputePolicy::Reject)
+                    .with_history();
+                let (accounts, _) = engine.analyze(transactions).unwrap();
+
+                let mut amounts = HashMap::new();
+                let mut expected = Decimal::ZERO;
+                for entry in (1..=CLIENTS).flat_map(|client| engine.history(client)).filter(|entry| entry.error.is_none()) {
+                    match entry.transaction_type {
+                        Deposit | Withdrawal => {
+                            let amount = entry.amount.unwrap();
+                            amounts.insert(entry.tx, amount);
+                            expected += if entry.transaction_type == Deposit { amount } else { -amount };
+                        }
+                        Chargeback => expected -= amounts[&entry.tx],
+                        _ => {}
+                    }
+                }
+
+                prop_assert_eq!(accounts.iter().map(|account| account.total).sum::<Decimal>(), expected);
+                prop_assert_eq!(engine.check_invariants().unwrap(), vec![]);
+            }
+        }
+    }
+
+    // Fails every write, as an unreachable remote store would
+    struct ReadOnlyTransactions;
+
+    impl TransactionStore for ReadOnlyTransactions {
+        fn record(&mut self, _tx: u32, _transaction: DisputableTransaction) -> Result<(), StoreError> {
+            Err(StoreError::Io(std::io::ErrorKind::PermissionDenied))
+        }
+
+        fn lookup(&self, _tx: u32) -> Result<Option<DisputableTransaction>, StoreError> {
+            Ok(None)
+        }
+
+        fn remove(&mut self, _tx: u32) -> Result<(), StoreError> {
+            Err(StoreError::Io(std::io::ErrorKind::PermissionDenied))
+        }
+
+        fn all(&self) -> Result<Vec<(u32, DisputableTransaction)>, StoreError> {
+            Ok(vec![])
+        }
+    }
+
+    // Creates accounts, but fails to update them
+    #[derive(Default)]
+    struct ReadOnlyAccounts(HashMap<u16, Account>);
+
+    impl AccountStore for ReadOnlyAccounts {
+        fn get(&self, client: u16) -> Result<Option<Account>, StoreError> {
+            AccountStore::get(&self.0, client)
+        }
+
+        fn create(&mut self, account: Account) -> Result<(), StoreError> {
+            AccountStore::create(&mut self.0, account)
+        }
+
+        fn update(&mut self, _account: Account) -> Result<(), StoreError> {
+            Err(StoreError::Io(std::io::ErrorKind::PermissionDenied))
+        }
+
+        fn all(&self) -> Result<Vec<Account>, StoreError> {
+            AccountStore::all(&self.0)
+        }
+    }
+
+    #[test]
+    fn test_store_failure() {
+        let path = temp_path("journal");
+        let mut engine = EngineExtended::new(HashMap::new(), ReadOnlyTransactions, HashSet::new())
+            .with_ledger()
+            .with_journal(Journal::open(&path).unwrap());
+
+        let (accounts, errors) = engine.analyze(vec![
+            Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+        ]).unwrap();
+        let journal = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert_that!(errors[0].kind, is(equal_to(ErrorKind::Store(StoreError::Io(std::io::ErrorKind::PermissionDenied)))));
+        assert_that!(accounts, is(equal_to(vec![Account::empty(CLIENT_ID)])));
+        assert_that!(engine.ledger().unwrap().posted(), is(equal_to(0)));
+        assert_that!(journal.as_str(), is(equal_to("")));
+    }
+
+    // Fails every write, as a full disk would
+    struct ReadOnlySeen;
+
+    impl SeenTransactions for ReadOnlySeen {
+        fn insert(&mut self, _tx: u32) -> Result<bool, StoreError> {
+            Err(StoreError::Io(std::io::ErrorKind::PermissionDenied))
         }
 
-        (self.account_repository.all(), errors)
+        fn contains(&self, _tx: u32) -> Result<bool, StoreError> {
+            Ok(false)
+        }
+
+        fn ids(&self) -> Box<dyn Iterator<Item=Result<u32, StoreError>> + '_> {
+            Box::new(std::iter::empty())
+        }
+    }
+
+    #[test]
+    fn test_seen_store_failure() {
+        let mut engine = EngineExtended::new(HashMap::new(), HashMap::new(), ReadOnlySeen);
+
+        let (accounts, errors) = engine.analyze(vec![Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0)))]).unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert_that!(errors[0].kind, is(equal_to(ErrorKind::Store(StoreError::Io(std::io::ErrorKind::PermissionDenied)))));
+        assert_that!(accounts, is(equal_to(vec![Account::empty(CLIENT_ID)])));
+        assert_that!(engine.transactions.lookup(1).unwrap(), is(equal_to(None)));
+    }
+
+    #[test]
+    fn test_account_store_failure() {
+        let mut engine = EngineExtended::new(ReadOnlyAccounts::default(), HashMap::new(), HashSet::new()).with_ledger();
+        engine.transactions.record(1, DisputableTransaction::new(&Transaction::new(Withdrawal, CLIENT_ID, 1, None), dec!(10.0))).unwrap();
+
+        let (accounts, errors) = engine.analyze(vec![
+            Transaction::new(Deposit, CLIENT_ID, 2, Some(dec!(100.0))),
+            Transaction::new(Dispute, CLIENT_ID, 1, None),
+        ]).unwrap();
+
+        assert_eq!(errors.len(), 2);
+        assert_that!(accounts, is(equal_to(vec![Account::empty(CLIENT_ID)])));
+        assert_that!(engine.transactions.lookup(2).unwrap(), is(equal_to(None)));
+        assert_that!(engine.transactions.lookup(1).unwrap().unwrap().state, is(equal_to(TransactionState::Applied)));
+        assert_that!(engine.ledger().unwrap().posted(), is(equal_to(0)));
+    }
+
+    #[test]
+    fn test_ledger() {
+        let mut engine = EngineSimple::default().with_ledger();
+
+        engine.analyze(vec![
+            Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+            Transaction::new(Withdrawal, CLIENT_ID, 2, Some(dec!(40.0))),
+            Transaction::new(Withdrawal, CLIENT_ID, 3, Some(dec!(100.0))),
+            Transaction::new(Dispute, CLIENT_ID, 2, None),
+            Transaction::new(Chargeback, CLIENT_ID, 2, None),
+            Transaction::new(Deposit, OTHER_CLIENT_ID, 4, Some(dec!(10.0))),
+            Transaction::new(Dispute, OTHER_CLIENT_ID, 4, None),
+            Transaction::new(Chargeback, OTHER_CLIENT_ID, 4, None),
+        ]).unwrap();
+        let ledger = engine.ledger().unwrap();
+
+        assert_that!(engine.verify_ledger(), is(equal_to(Ok(()))));
+        assert_that!(ledger.posted(), is(equal_to(7)));
+        assert_that!(ledger.balance(LedgerAccount::ExternalFunding), is(equal_to(dec!(-110.0))));
+        assert_that!(ledger.balance(LedgerAccount::ChargebackLoss), is(equal_to(dec!(10.0))));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_ledger_write_failure() {
+        let mut engine = EngineSimple::default().with_ledger_writer(std::fs::File::create("/dev/full").unwrap());
+        engine.analyze_transaction(Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(10.0)))).unwrap();
+
+        assert_that!(matches!(engine.complete_ledger(), Err(LedgerError::Store(_))), is(true));
+    }
+
+    #[test]
+    fn test_ledger_overflow() {
+        let mut engine = EngineSimple::default()
+            .with_amount_limits(AmountLimits { max_deposit: Decimal::MAX, max_withdrawal: Decimal::MAX })
+            .with_ledger();
+
+        let (accounts, errors) = engine.analyze(vec![
+            Transaction::new(Deposit, CLIENT_ID, 1, Some(Decimal::MAX)),
+            Transaction::new(Deposit, OTHER_CLIENT_ID, 2, Some(Decimal::MAX)),
+        ]).unwrap();
+
+        assert_that!(accounts, is(equal_to(vec![
+            Account::new(CLIENT_ID, Decimal::MAX, dec!(0), false),
+            Account::empty(OTHER_CLIENT_ID),
+        ])));
+        assert_that!(errors.len(), is(equal_to(1)));
+        assert_that!(errors[0].kind, is(equal_to(ErrorKind::Overflow)));
+        assert_that!(engine.verify_ledger(), is(equal_to(Ok(()))));
+    }
+
+    #[test]
+    fn test_ledger_opening_balances() {
+        let mut engine = EngineSimple::default().with_ledger();
+        engine.load_accounts("client,available,held,total,locked\n123,10,5,15,false\n".as_bytes()).unwrap();
+
+        engine.analyze(vec![
+            Transaction::new(Withdrawal, CLIENT_ID, 1, Some(dec!(10.0))),
+        ]).unwrap();
+
+        assert_that!(engine.verify_ledger(), is(equal_to(Ok(()))));
+        assert_that!(engine.ledger().unwrap().balance(LedgerAccount::ClientHeld(CLIENT_ID)), is(equal_to(dec!(5))));
+    }
+
+    #[test]
+    fn test_invariants() {
+        let mut engine = EngineSimple::default();
+        engine.load_accounts("client,available,held,total,locked\n456,10,5,15,false\n".as_bytes()).unwrap();
+
+        engine.analyze(vec![
+            Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+            Transaction::new(Withdrawal, CLIENT_ID, 2, Some(dec!(40.0))),
+            Transaction::new(Dispute, CLIENT_ID, 1, None),
+            Transaction::new(Dispute, CLIENT_ID, 2, None),
+            Transaction::new(Resolve, CLIENT_ID, 2, None),
+            Transaction::new(Deposit, OTHER_CLIENT_ID, 3, Some(dec!(10.0))),
+        ]).unwrap();
+
+        assert_that!(engine.check_invariants(), is(equal_to(Ok(vec![]))));
+    }
+
+    #[test]
+    fn test_history() {
+        let mut engine = EngineSimple::default().with_history();
+
+        engine.analyze(vec![
+            Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+            Transaction::new(Deposit, OTHER_CLIENT_ID, 2, Some(dec!(10.0))),
+            Transaction::new(Withdrawal, CLIENT_ID, 3, Some(dec!(200.0))),
+            Transaction::new(Dispute, CLIENT_ID, 1, None),
+        ]).unwrap();
+        let history = engine.history(CLIENT_ID);
+
+        assert_that!(history.len(), is(equal_to(3)));
+        assert_that!(history.iter().map(|entry| entry.tx).collect::<Vec<_>>(), is(equal_to(vec![1, 3, 1])));
+        assert_that!(history[1].error, is(equal_to(Some(ErrorKind::InsufficientAvailableFunds))));
+        assert_that!(history[1].available, is(equal_to(dec!(100.0))));
+        assert_that!(history[2].error, is(equal_to(None)));
+        assert_that!(history[2].held, is(equal_to(dec!(100.0))));
+        assert_that!(engine.history(789).len(), is(equal_to(0)));
+    }
+
+    #[test]
+    fn test_history_for_client() {
+        let mut engine = EngineSimple::default().with_history_for(CLIENT_ID);
+        engine.analyze(vec![
+            Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+            Transaction::new(Deposit, OTHER_CLIENT_ID, 2, Some(dec!(10.0))),
+        ]).unwrap();
+
+        assert_that!(engine.history(CLIENT_ID).len(), is(equal_to(1)));
+        assert_that!(engine.history(OTHER_CLIENT_ID).len(), is(equal_to(0)));
+    }
+
+    #[test]
+    fn test_no_history() {
+        let mut engine = EngineSimple::default();
+
+        engine.analyze(vec![
+            Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+        ]).unwrap();
+
+        assert_that!(engine.history(CLIENT_ID).len(), is(equal_to(0)));
+    }
+
+    fn journaled_transactions() -> Vec<Transaction> {
+        vec![
+            Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(100.0))),
+            Transaction::new(Withdrawal, CLIENT_ID, 2, Some(dec!(200.0))),
+            Transaction::new(Dispute, CLIENT_ID, 1, None),
+            Transaction::new(Resolve, CLIENT_ID, 1, None),
+            Transaction::new(Withdrawal, CLIENT_ID, 3, Some(dec!(30.0))),
+            Transaction::new(Deposit, OTHER_CLIENT_ID, 4, Some(dec!(10.0))),
+        ]
+    }
+
+    #[test]
+    fn test_recover_from_journal() {
+        let path = temp_path("journal");
+        let mut transactions = journaled_transactions();
+        let remaining = transactions.split_off(4);
+
+        let mut crashed = EngineSimple::default().with_journal(Journal::open(&path).unwrap());
+        crashed.analyze(transactions).unwrap();
+
+        let mut recovered = EngineSimple::default().with_journal(Journal::open(&path).unwrap());
+        let skipped = recovered.recover().unwrap();
+        let (accounts, errors) = recovered.analyze(remaining).unwrap();
+        let (expected, _) = EngineSimple::default().analyze(journaled_transactions()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_that!(skipped, is(equal_to(4)));
+        assert_that!(accounts, is(equal_to(expected)));
+        assert_eq!(errors.len(), 0);
+    }
+
+    #[test]
+    fn test_recover_rejected_ids() {
+        let path = temp_path("journal");
+
+        let mut crashed = EngineSimple::default().with_journal(Journal::open(&path).unwrap());
+        crashed.analyze(vec![Transaction::new(Withdrawal, CLIENT_ID, 1, Some(dec!(10.0)))]).unwrap();
+
+        let mut recovered = EngineSimple::default().with_journal(Journal::open(&path).unwrap());
+        recovered.recover().unwrap();
+        let (_, errors) = recovered.analyze(vec![Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(10.0)))]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert_that!(errors[0].kind, is(equal_to(ErrorKind::DuplicateTransaction)));
+    }
+
+    #[test]
+    fn test_recover_completed_journal() {
+        let path = temp_path("journal");
+
+        let mut completed = EngineSimple::default().with_journal(Journal::open(&path).unwrap());
+        completed.analyze(journaled_transactions()).unwrap();
+        completed.complete_journal().unwrap();
+
+        let mut next = EngineSimple::default().with_journal(Journal::open(&path).unwrap());
+        let skipped = next.recover().unwrap();
+        let (accounts, _) = next.analyze(vec![Transaction::new(Deposit, CLIENT_ID, 1, Some(dec!(5.0)))]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_that!(skipped, is(equal_to(0)));
+        assert_that!(accounts, is(equal_to(vec![Account::new(CLIENT_ID, dec!(5.0), dec!(0), false)])));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_journal_failure_on_rejection() {
+        let mut engine = EngineSimple::default().with_journal(Journal::open("/dev/full").unwrap());
+
+        let result = engine.analyze_transaction(Transaction::new(Withdrawal, CLIENT_ID, 1, Some(dec!(10.0))));
+
+        assert_that!(result.unwrap_err().kind.is_fatal(), is(true));
+    }
+
+    #[test]
+    fn test_recover_diverged_journal() {
+        let path = temp_path("journal");
+        std::fs::write(&path, "deposit,123,1,10,10,0,10
+deposit,123,2,10,,,
+").unwrap();
+
+        let mut engine = EngineSimple::default().with_journal(Journal::open(&path).unwrap());
+        let result = engine.recover();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_that!(result.unwrap_err().to_string().as_str(), is(equal_to("Journal entry 1 could not be replayed with the same result")));
+    }
+
+    fn find_account(accounts: &[Account], client: u16) -> &Account {
+        accounts.iter().find(|account| account.client == client).unwrap()
+    }
+
+    fn assert_account(account: &Account, available: Decimal, held: Decimal, total: Decimal, locked: bool) {
+        assert_that!(account.available, is(equal_to(available)));
+        assert_that!(account.held, is(equal_to(held)));
+        assert_that!(account.total, is(equal_to(total)));
+        assert_that!(account.locked, is(locked));
     }
 }
@@ -0,0 +1,466 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::collections::btree_map::Entry;
+use std::panic;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::{Account, Transaction};
+use crate::account::{sort_accounts, LockPolicy};
+use crate::engine::{Engine, WithdrawalDisputePolicy};
+use crate::engine_simple::EngineSimple;
+use crate::error::{EngineError, ErrorKind};
+use crate::seen_transactions::TransactionBitmap;
+use crate::store::StoreError;
+use crate::validation::AmountLimits;
+
+// Transactions are sent to the workers in batches, since sending them one by one costs more than analyzing them.
+// When a batch is full, the batches of the other shards are sent along, so that the errors of a shard with
+// few transactions are not held back.
+const BATCH_SIZE: usize = 1024;
+
+enum Message {
+    // The position in the input, to report errors in the same order as a single engine, and whether the id
+    // has to be reserved first, since it was already used, possibly on another shard
+    Analyze(usize, Transaction, bool),
+    // A dispute, resolve or chargeback of a transaction of this shard, analyzed on the shard of its client:
+    // only this shard can tell that the transaction belongs to another client
+    Lookup(usize, Transaction),
+}
+
+// The errors of a batch, once a worker has analyzed it
+struct Analyzed {
+    shard: usize,
+    errors: Vec<(usize, EngineError)>,
+}
+
+// Keeps the errors until all the transactions before them have been analyzed, to report them in input order
+struct Progress {
+    // First position of every batch sent to each shard and not analyzed yet, oldest first
+    sent: Vec<VecDeque<usize>>,
+    // First position of the batch of each shard that has not been sent yet
+    buffered: Vec<Option<usize>>,
+    errors: BTreeMap<usize, EngineError>,
+}
+
+impl Progress {
+    fn new(shards: usize) -> Self {
+        Self { sent: vec![VecDeque::new(); shards], buffered: vec![None; shards], errors: BTreeMap::new() }
+    }
+
+    fn buffer(&mut self, shard: usize, position: usize) {
+        self.buffered[shard].get_or_insert(position);
+    }
+
+    fn send(&mut self, shard: usize) {
+        if let Some(position) = self.buffered[shard].take() {
+            self.sent[shard].push_back(position);
+        }
+    }
+
+    // Batches of the same shard are analyzed in the order they are sent
+    fn analyzed(&mut self, analyzed: Analyzed) {
+        self.sent[analyzed.shard].pop_front();
+
+        for (position, err) in analyzed.errors {
+            match self.errors.entry(position) {
+                Entry::Vacant(entry) => {
+                    entry.insert(err);
+                }
+                Entry::Occupied(mut entry) => {
+                    let first = first_error(entry.get().clone(), err);
+                    entry.insert(first);
+                }
+            }
+        }
+    }
+
+    // Reports the errors before the first position that has not been analyzed yet
+    fn report<F: FnMut(EngineError)>(&mut self, on_error: &mut F) {
+        let pending = self.sent.iter().filter_map(|sent| sent.front()).chain(self.buffered.iter().flatten()).min();
+        let later = match pending {
+            Some(position) => self.errors.split_off(position),
+            None => BTreeMap::new(),
+        };
+
+        for (_, err) in std::mem::replace(&mut self.errors, later) {
+            on_error(err);
+        }
+    }
+}
+
+// Clients are independent, so they are partitioned into shards, each one with its own engine and stores, and
+// analyzed by its own thread. All the transactions of a client go to the same shard, in input order.
+//
+// Transaction ids are unique across clients, so the ids used by deposits and withdrawals are tracked here,
+// and an id that was already used is reserved on the shard before it sees it again. Disputing a transaction of
+// a client on another shard is also looked up on that shard, so that it is reported as a client mismatch,
+// like on a single engine.
+pub struct EngineSharded {
+    shards: Vec<EngineSimple>,
+    router: Router,
+}
+
+// Decides on which shard every transaction is analyzed
+struct Router {
+    // Ids used by each shard, in the same bitmap as the extended engine. Only the first use of an id counts,
+    // so every id belongs to a single shard.
+    claimed: Vec<TransactionBitmap>,
+}
+
+struct Route {
+    shard: usize,
+    // Whether the id has to be reserved first, since it was already used, possibly on another shard
+    reserve: bool,
+    // The other shard the disputed transaction belongs to, if any
+    owner: Option<usize>,
+}
+
+impl Router {
+    fn shard_of(&self, client: u16) -> usize {
+        usize::from(client) % self.claimed.len()
+    }
+
+    fn owner_of(&self, tx: u32) -> Option<usize> {
+        self.claimed.iter().position(|claimed| claimed.is_set(tx))
+    }
+
+    fn route(&mut self, transaction: &Transaction) -> Route {
+        let shard = self.shard_of(transaction.client);
+        let owner = self.owner_of(transaction.tx);
+
+        if transaction.uses_id() {
+            if owner.is_none() {
+                self.claimed[shard].set(transaction.tx);
+            }
+
+            // Reserving an id twice on the same shard is harmless
+            return Route { shard, reserve: owner.is_some(), owner: None };
+        }
+
+        Route { shard, reserve: false, owner: owner.filter(|owner| *owner != shard) }
+    }
+}
+
+impl EngineSharded {
+    // At least one shard is always used
+    pub fn new(shards: usize) -> Self {
+        let shards = shards.max(1);
+
+        Self {
+            shards: (0..shards).map(|_| EngineSimple::default()).collect(),
+            router: Router { claimed: (0..shards).map(|_| TransactionBitmap::new()).collect() },
+        }
+    }
+
+    pub fn with_lock_policy(mut self, lock_policy: LockPolicy) -> Self {
+        self.shards = self.shards.into_iter().map(|shard| shard.with_lock_policy(lock_policy)).collect();
+        self
+    }
+
+    pub fn with_withdrawal_dispute_policy(mut self, withdrawal_dispute_policy: WithdrawalDisputePolicy) -> Self {
+        self.shards = self.shards.into_iter().map(|shard| shard.with_withdrawal_dispute_policy(withdrawal_dispute_policy)).collect();
+        self
+    }
+
+    pub fn with_amount_limits(mut self, amount_limits: AmountLimits) -> Self {
+        self.shards = self.shards.into_iter().map(|shard| shard.with_amount_limits(amount_limits)).collect();
+        self
+    }
+}
+
+fn analyze_on(engine: &mut EngineSimple, transaction: Transaction, reserve: bool) -> Result<(), EngineError> {
+    if reserve {
+        engine.reserve_transaction(transaction.tx)
+            .map_err(|err| EngineError::new(ErrorKind::Store(err), &transaction, transaction.amount))?;
+    }
+
+    engine.analyze_transaction(transaction)
+}
+
+// Only reports a transaction that belongs to another client, since the shard of the client reports anything else
+fn look_up_on(engine: &EngineSimple, transaction: &Transaction) -> Result<(), EngineError> {
+    let disputable = engine.lookup_transaction(transaction.tx)
+        .map_err(|err| EngineError::new(ErrorKind::Store(err), transaction, transaction.amount))?;
+
+    match disputable {
+        Some(disputable) if disputable.client != transaction.client => {
+            Err(EngineError::new(ErrorKind::ClientMismatch, transaction, Some(disputable.amount)))
+        }
+        _ => Ok(()),
+    }
+}
+
+// The error a single engine would report, out of the ones of the shard of the client and of the shard of the
+// transaction: the former can only tell that the transaction is unknown there, while failing before looking it up
+// (e.g. to create the account) comes first
+fn first_error(err: EngineError, other: EngineError) -> EngineError {
+    let rank = |err: &EngineError| match err.kind {
+        ErrorKind::UnknownTransaction => 0,
+        ErrorKind::ClientMismatch => 1,
+        _ => 2,
+    };
+
+    if rank(&other) > rank(&err) { other } else { err }
+}
+
+impl Engine for EngineSharded {
+    // Analyzes the transaction on the current thread
+    fn analyze_transaction(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let route = self.router.route(&transaction);
+        let result = analyze_on(&mut self.shards[route.shard], transaction, route.reserve);
+
+        match (result, route.owner) {
+            (Err(err), Some(owner)) => match look_up_on(&self.shards[owner], &transaction) {
+                Err(other) => Err(first_error(err, other)),
+                Ok(()) => Err(err),
+            },
+            (result, _) => result,
+        }
+    }
+
+    fn accounts(&self) -> Result<Vec<Account>, StoreError> {
+        let mut accounts = vec![];
+        for shard in &self.shards {
+            accounts.extend(shard.accounts()?);
+        }
+        sort_accounts(&mut accounts, &[]);
+
+        Ok(accounts)
+    }
+
+    fn unlock(&mut self, client: u16, reason: &str) -> Result<(), ErrorKind> {
+        let shard = self.router.shard_of(client);
+
+        self.shards[shard].unlock(client, reason)
+    }
+
+    // Analyzes every shard on its own thread. Errors are reported in input order, as soon as all the transactions
+    // before them have been analyzed.
+    fn analyze_with<I, F>(&mut self, transactions: I, mut on_error: F)
+        where I: IntoIterator<Item=Transaction>, F: FnMut(EngineError) {
+        let shards = &mut self.shards;
+        let router = &mut self.router;
+
+        thread::scope(|scope| {
+            let (analyzed_sender, analyzed) = mpsc::channel::<Analyzed>();
+            let mut senders = vec![];
+            let mut workers = vec![];
+
+            for (shard, engine) in shards.iter_mut().enumerate() {
+                let (sender, receiver) = mpsc::sync_channel::<Vec<Message>>(16);
+                let analyzed_sender = analyzed_sender.clone();
+                senders.push(sender);
+
+                workers.push(scope.spawn(move || {
+                    for batch in receiver {
+                        let mut errors = vec![];
+
+                        for message in batch {
+                            match message {
+                                Message::Analyze(position, transaction, reserve) => {
+                                    if let Err(err) = analyze_on(engine, transaction, reserve) {
+                                        errors.push((position, err));
+                                    }
+                                }
+                                Message::Lookup(position, transaction) => {
+                                    if let Err(err) = look_up_on(engine, &transaction) {
+                                        errors.push((position, err));
+                                    }
+                                }
+                            }
+                        }
+
+                        // The receiver lives until every worker is done
+                        let _ = analyzed_sender.send(Analyzed { shard, errors });
+                    }
+                }));
+            }
+            drop(analyzed_sender);
+
+            let mut batches: Vec<Vec<Message>> = (0..workers.len()).map(|_| Vec::with_capacity(BATCH_SIZE)).collect();
+            let mut progress = Progress::new(workers.len());
+
+            'input: for (position, transaction) in transactions.into_iter().enumerate() {
+                let route = router.route(&transaction);
+
+                if let Some(owner) = route.owner {
+                    batches[owner].push(Message::Lookup(position, transaction));
+                    progress.buffer(owner, position);
+                }
+
+                batches[route.shard].push(Message::Analyze(position, transaction, route.reserve));
+                progress.buffer(route.shard, position);
+
+                if batches.iter().all(|batch| batch.len() < BATCH_SIZE) {
+                    continue;
+                }
+
+                for (index, batch) in batches.iter_mut().enumerate().filter(|(_, batch)| !batch.is_empty()) {
+                    progress.send(index);
+
+                    // A worker only stops receiving if it panicked, which is reported when joining it
+                    if senders[index].send(std::mem::replace(batch, Vec::with_capacity(BATCH_SIZE))).is_err() {
+                        break 'input;
+                    }
+                }
+
+                for batch in analyzed.try_iter() {
+                    progress.analyzed(batch);
+                }
+                progress.report(&mut on_error);
+            }
+
+            for (index, (sender, batch)) in senders.into_iter().zip(batches).enumerate() {
+                if !batch.is_empty() {
+                    progress.send(index);
+                    let _ = sender.send(batch);
+                }
+            }
+
+            for batch in analyzed.iter() {
+                progress.analyzed(batch);
+                progress.report(&mut on_error);
+            }
+
+            for worker in workers {
+                worker.join().unwrap_or_else(|err| panic::resume_unwind(err));
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use crate::transaction::TransactionType::*;
+
+    use super::*;
+
+    #[test]
+    fn test_same_as_single_engine() {
+        let transactions = vec![
+            Transaction::new(Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Deposit, 2, 2, Some(dec!(5))),
+            Transaction::new(Withdrawal, 3, 3, Some(dec!(1))),
+            Transaction::new(Withdrawal, 1, 4, Some(dec!(4))),
+            Transaction::new(Dispute, 2, 2, None),
+            Transaction::new(Chargeback, 2, 2, None),
+            Transaction::new(Deposit, 2, 5, Some(dec!(1))),
+        ];
+
+        let expected = EngineSimple::default().analyze(transactions.clone()).unwrap();
+        let actual = EngineSharded::new(2).analyze(transactions).unwrap();
+
+        assert_that!(actual, is(equal_to(expected)));
+    }
+
+    #[test]
+    fn test_duplicate_ids_across_shards() {
+        let mut engine = EngineSharded::new(2);
+
+        let (accounts, errors) = engine.analyze(vec![
+            Transaction::new(Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Deposit, 2, 1, Some(dec!(5))),
+            Transaction::new(Withdrawal, 2, 1, None),
+        ]).unwrap();
+        let duplicate = engine.analyze_transaction(Transaction::new(Deposit, 2, 1, Some(dec!(5))));
+
+        assert_that!(accounts, is(equal_to(vec![Account::new(1, dec!(10), dec!(0), false), Account::empty(2)])));
+        assert_that!(errors.iter().map(|err| err.kind).collect::<Vec<_>>(), is(equal_to(vec![ErrorKind::DuplicateTransaction, ErrorKind::MissingAmount])));
+        assert_that!(duplicate.unwrap_err().kind, is(equal_to(ErrorKind::DuplicateTransaction)));
+    }
+
+    #[test]
+    fn test_client_mismatch_across_shards() {
+        let transactions = vec![
+            Transaction::new(Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Deposit, 2, 2, Some(dec!(5000))),
+            Transaction::new(Dispute, 2, 1, None),
+            Transaction::new(Dispute, 3, 2, None),
+            Transaction::new(Dispute, 4, 3, None),
+        ];
+        let limits = AmountLimits { max_deposit: dec!(1000), max_withdrawal: dec!(1000) };
+
+        let expected = EngineSimple::default().with_amount_limits(limits).analyze(transactions.clone()).unwrap();
+        let actual = EngineSharded::new(2).with_amount_limits(limits).analyze(transactions.clone()).unwrap();
+        let mut one_by_one = EngineSharded::new(2).with_amount_limits(limits);
+        let errors: Vec<_> = transactions.into_iter().filter_map(|transaction| one_by_one.analyze_transaction(transaction).err()).collect();
+
+        assert_that!(expected.1.iter().map(|err| err.kind).collect::<Vec<_>>(),
+            is(equal_to(vec![ErrorKind::AmountTooLarge, ErrorKind::ClientMismatch, ErrorKind::UnknownTransaction, ErrorKind::UnknownTransaction])));
+        assert_that!(actual, is(equal_to(expected.clone())));
+        assert_that!(errors, is(equal_to(expected.1)));
+    }
+
+    #[test]
+    fn test_invalid_amounts_across_shards() {
+        let transactions = vec![
+            Transaction::new(Deposit, 1, 1, Some(dec!(5000))),
+            Transaction::new(Deposit, 2, 1, Some(dec!(10))),
+            Transaction::new(Withdrawal, 3, 2, Some(dec!(-1))),
+        ];
+        let limits = AmountLimits { max_deposit: dec!(1000), max_withdrawal: dec!(1000) };
+
+        let expected = EngineSimple::default().with_amount_limits(limits).analyze(transactions.clone()).unwrap();
+        let actual = EngineSharded::new(3).with_amount_limits(limits).analyze(transactions).unwrap();
+
+        assert_that!(actual, is(equal_to(expected)));
+    }
+
+    #[test]
+    fn test_errors_in_input_order() {
+        let transactions: Vec<Transaction> = (0..5000).map(|tx| Transaction::new(Withdrawal, (tx % 7) as u16, tx, Some(dec!(1)))).collect();
+
+        let (_, errors) = EngineSharded::new(4).analyze(transactions).unwrap();
+
+        assert_that!(errors.iter().map(|err| err.tx).collect::<Vec<_>>(), is(equal_to((0..5000).collect::<Vec<_>>())));
+    }
+
+    #[test]
+    fn test_errors_before_end_of_input() {
+        let total = 64 * BATCH_SIZE;
+        let consumed = std::cell::Cell::new(0);
+        let transactions = (0..total as u32).map(|tx| Transaction::new(Withdrawal, (tx % 2) as u16, tx, Some(dec!(1))))
+            .inspect(|_| consumed.set(consumed.get() + 1));
+        let mut reported_at = vec![];
+
+        EngineSharded::new(2).analyze_with(transactions, |_| reported_at.push(consumed.get()));
+
+        assert_that!(reported_at.len(), is(equal_to(total)));
+        assert_that!(reported_at[0] < total, is(true));
+    }
+
+    #[test]
+    fn test_unlock() {
+        let mut engine = EngineSharded::new(3);
+        engine.analyze(vec![
+            Transaction::new(Deposit, 5, 1, Some(dec!(10))),
+            Transaction::new(Dispute, 5, 1, None),
+            Transaction::new(Chargeback, 5, 1, None),
+        ]).unwrap();
+
+        assert_that!(engine.unlock(5, "Checked with the bank"), is(equal_to(Ok(()))));
+        assert_that!(engine.unlock(6, "Unknown"), is(equal_to(Err(ErrorKind::UnknownClient))));
+    }
+
+    mod properties {
+        use proptest::prelude::*;
+
+        use crate::transaction::tests::transaction;
+
+        use super::*;
+
+        proptest! {
+            #[test]
+            fn test_same_results_as_single_engine(transactions in proptest::collection::vec(transaction(8, 40), 0..300), shards in 1..6usize) {
+                let (expected_accounts, expected_errors) = EngineSimple::default().analyze(transactions.clone()).unwrap();
+                let (accounts, errors) = EngineSharded::new(shards).analyze(transactions).unwrap();
+
+                prop_assert_eq!(accounts, expected_accounts);
+                prop_assert_eq!(errors, expected_errors);
+            }
+        }
+    }
+}
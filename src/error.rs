@@ -0,0 +1,159 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::Transaction;
+use crate::store::StoreError;
+use crate::transaction::TransactionType;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorKind {
+    InsufficientAvailableFunds,
+    InsufficientHeldFunds,
+    MissingAmount,
+    UnknownTransaction,
+    AlreadyDisputed,
+    NotDisputed,
+    AlreadyChargedBack,
+    AccountLocked,
+    AccountNotLocked,
+    UnknownClient,
+    ClientMismatch,
+    DuplicateTransaction,
+    WithdrawalNotDisputable,
+    ExcessPrecision,
+    NonPositiveAmount,
+    AmountTooLarge,
+    Overflow,
+    Store(StoreError),
+    // The journal could not be written, so nothing else can be analyzed safely
+    Journal(StoreError),
+    // The ledger could not be written, after the stores have already been updated
+    Ledger(StoreError),
+}
+
+impl ErrorKind {
+    // Fatal errors must stop the analysis, rather than being reported like a rejection
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, ErrorKind::Journal(_) | ErrorKind::Ledger(_))
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::InsufficientAvailableFunds => write!(f, "Insufficient available funds"),
+            ErrorKind::InsufficientHeldFunds => write!(f, "Insufficient held funds"),
+            ErrorKind::MissingAmount => write!(f, "Missing amount"),
+            ErrorKind::UnknownTransaction => write!(f, "Unknown transaction"),
+            ErrorKind::AlreadyDisputed => write!(f, "Already disputed"),
+            ErrorKind::NotDisputed => write!(f, "Transaction is not disputed"),
+            ErrorKind::AlreadyChargedBack => write!(f, "Transaction has already been charged back"),
+            ErrorKind::AccountLocked => write!(f, "Account is locked"),
+            ErrorKind::AccountNotLocked => write!(f, "Account is not locked"),
+            ErrorKind::UnknownClient => write!(f, "Unknown client"),
+            ErrorKind::ClientMismatch => write!(f, "Transaction belongs to another client"),
+            ErrorKind::DuplicateTransaction => write!(f, "Duplicate transaction"),
+            ErrorKind::WithdrawalNotDisputable => write!(f, "Withdrawals cannot be disputed"),
+            ErrorKind::ExcessPrecision => write!(f, "Amount has too many decimal places"),
+            ErrorKind::NonPositiveAmount => write!(f, "Amount must be positive"),
+            ErrorKind::AmountTooLarge => write!(f, "Amount is over the limit"),
+            ErrorKind::Overflow => write!(f, "Balance would overflow"),
+            ErrorKind::Store(err) => write!(f, "Store failure: {}", err),
+            ErrorKind::Journal(err) => write!(f, "Journal failure: {}", err),
+            ErrorKind::Ledger(err) => write!(f, "Ledger failure: {}", err),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EngineError {
+    pub kind: ErrorKind,
+    pub transaction_type: TransactionType,
+    pub client: u16,
+    pub tx: u32,
+    // The amount that could not be applied, if it is known
+    pub amount: Option<Decimal>,
+}
+
+impl EngineError {
+    pub fn new(kind: ErrorKind, transaction: &Transaction, amount: Option<Decimal>) -> Self {
+        Self {
+            kind,
+            transaction_type: transaction.transaction_type,
+            client: transaction.client,
+            tx: transaction.tx,
+            amount,
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tx = self.tx;
+
+        match (self.kind, self.transaction_type) {
+            (ErrorKind::MissingAmount, _) => write!(f, "Missing amount for transaction \"{}\"", tx),
+            (ErrorKind::UnknownTransaction, TransactionType::Dispute) => write!(f, "Could not find applied transaction \"{}\" to dispute", tx),
+            (ErrorKind::UnknownTransaction, TransactionType::Resolve) => write!(f, "Could not find disputed transaction \"{}\" to resolve", tx),
+            (ErrorKind::UnknownTransaction, TransactionType::Chargeback) => write!(f, "Could not find disputed transaction \"{}\" to charge back", tx),
+            (ErrorKind::AlreadyDisputed, _) => write!(f, "Could not dispute same transaction \"{}\" twice", tx),
+            (kind, TransactionType::Deposit | TransactionType::Withdrawal) => write!(f, "Error when handling transaction \"{}\": {}", tx, kind),
+            (kind, TransactionType::Dispute) => write!(f, "Could not dispute transaction \"{}\": {}", tx, kind),
+            (kind, TransactionType::Resolve) => write!(f, "Could not resolve disputed transaction \"{}\": {}", tx, kind),
+            (kind, TransactionType::Chargeback) => write!(f, "Could not charge back disputed transaction \"{}\": {}", tx, kind),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use crate::transaction::TransactionType::*;
+
+    use super::*;
+
+    fn message(kind: ErrorKind, transaction: Transaction) -> String {
+        EngineError::new(kind, &transaction, transaction.amount).to_string()
+    }
+
+    #[test]
+    fn test_display_withdrawal_error() {
+        let transaction = Transaction::new(Withdrawal, 1, 4, Some(dec!(1.5)));
+
+        assert_that!(message(ErrorKind::InsufficientAvailableFunds, transaction),
+            is(equal_to("Error when handling transaction \"4\": Insufficient available funds".to_string())));
+    }
+
+    #[test]
+    fn test_display_missing_amount() {
+        assert_that!(message(ErrorKind::MissingAmount, Transaction::new(Deposit, 1, 4, None)),
+            is(equal_to("Missing amount for transaction \"4\"".to_string())));
+    }
+
+    #[test]
+    fn test_display_unknown_transaction() {
+        assert_that!(message(ErrorKind::UnknownTransaction, Transaction::new(Dispute, 1, 4, None)),
+            is(equal_to("Could not find applied transaction \"4\" to dispute".to_string())));
+        assert_that!(message(ErrorKind::UnknownTransaction, Transaction::new(Resolve, 1, 4, None)),
+            is(equal_to("Could not find disputed transaction \"4\" to resolve".to_string())));
+        assert_that!(message(ErrorKind::UnknownTransaction, Transaction::new(Chargeback, 1, 4, None)),
+            is(equal_to("Could not find disputed transaction \"4\" to charge back".to_string())));
+    }
+
+    #[test]
+    fn test_display_double_dispute() {
+        assert_that!(message(ErrorKind::AlreadyDisputed, Transaction::new(Dispute, 1, 4, None)),
+            is(equal_to("Could not dispute same transaction \"4\" twice".to_string())));
+    }
+
+    #[test]
+    fn test_display_chargeback_error() {
+        assert_that!(message(ErrorKind::InsufficientHeldFunds, Transaction::new(Chargeback, 1, 4, None)),
+            is(equal_to("Could not charge back disputed transaction \"4\": Insufficient held funds".to_string())));
+    }
+}
@@ -0,0 +1,232 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use rust_decimal::Decimal;
+
+use crate::store::{StoreError, TransactionStore};
+use crate::transaction::{DisputableTransaction, TransactionType};
+use crate::transaction_state::TransactionState;
+
+const RECORD_SIZE: u64 = 24;
+const PRESENT: u8 = 1;
+
+// Keeps the applied transactions on disk, so that they can be disputed long after they have been applied,
+// without holding them in memory.
+// Every transaction is stored in a fixed size record at an offset given by its id, so lookups don't need an index.
+// Ids that have never been used are holes in a sparse file, and take no space on disk.
+pub struct FileTransactionStore {
+    file: File,
+}
+
+impl FileTransactionStore {
+    // Opens an existing store, or creates an empty one
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+
+        Ok(Self { file })
+    }
+
+    fn write_record(&self, tx: u32, record: &[u8; RECORD_SIZE as usize]) -> Result<(), StoreError> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(tx as u64 * RECORD_SIZE))?;
+        file.write_all(record)?;
+
+        Ok(())
+    }
+}
+
+impl TransactionStore for FileTransactionStore {
+    fn record(&mut self, tx: u32, transaction: DisputableTransaction) -> Result<(), StoreError> {
+        self.write_record(tx, &encode(&transaction))
+    }
+
+    fn lookup(&self, tx: u32) -> Result<Option<DisputableTransaction>, StoreError> {
+        let mut file = &self.file;
+        let mut record = [0; RECORD_SIZE as usize];
+
+        file.seek(SeekFrom::Start(tx as u64 * RECORD_SIZE))?;
+        match file.read_exact(&mut record) {
+            Ok(_) => decode(&record),
+            // Past the end of the file, so never recorded
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn remove(&mut self, tx: u32) -> Result<(), StoreError> {
+        if self.lookup(tx)?.is_none() {
+            return Ok(());
+        }
+
+        self.write_record(tx, &[0; RECORD_SIZE as usize])
+    }
+
+    // Scans the whole file, which can take a while for high ids
+    fn all(&self) -> Result<Vec<(u32, DisputableTransaction)>, StoreError> {
+        let mut file = &self.file;
+        let mut reader = io::BufReader::new(file);
+        let mut record = [0; RECORD_SIZE as usize];
+        let mut transactions = vec![];
+
+        file.seek(SeekFrom::Start(0))?;
+        for tx in 0..=u32::MAX {
+            match reader.read_exact(&mut record) {
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+
+            if let Some(transaction) = decode(&record)? {
+                transactions.push((tx, transaction));
+            }
+        }
+
+        Ok(transactions)
+    }
+}
+
+// Layout: presence (1 byte), type (1 byte), state (1 byte), padding (1 byte), client (2 bytes),
+// padding (2 bytes), amount (16 bytes)
+fn encode(transaction: &DisputableTransaction) -> [u8; RECORD_SIZE as usize] {
+    let mut record = [0; RECORD_SIZE as usize];
+
+    record[0] = PRESENT;
+    record[1] = match transaction.transaction_type {
+        TransactionType::Withdrawal => 1,
+        _ => 0,
+    };
+    record[2] = match transaction.state {
+        TransactionState::Applied => 0,
+        TransactionState::Disputed => 1,
+        TransactionState::Resolved => 2,
+        TransactionState::ChargedBack => 3,
+    };
+    record[4..6].copy_from_slice(&transaction.client.to_le_bytes());
+    record[8..24].copy_from_slice(&transaction.amount.serialize());
+
+    record
+}
+
+fn decode(record: &[u8; RECORD_SIZE as usize]) -> Result<Option<DisputableTransaction>, StoreError> {
+    match record[0] {
+        0 => return Ok(None),
+        PRESENT => {}
+        _ => return Err(StoreError::Corrupted),
+    }
+
+    let transaction_type = match record[1] {
+        0 => TransactionType::Deposit,
+        1 => TransactionType::Withdrawal,
+        _ => return Err(StoreError::Corrupted),
+    };
+    let state = match record[2] {
+        0 => TransactionState::Applied,
+        1 => TransactionState::Disputed,
+        2 => TransactionState::Resolved,
+        3 => TransactionState::ChargedBack,
+        _ => return Err(StoreError::Corrupted),
+    };
+    let client = u16::from_le_bytes([record[4], record[5]]);
+    let mut amount = [0; 16];
+    amount.copy_from_slice(&record[8..24]);
+
+    Ok(Some(DisputableTransaction {
+        transaction_type,
+        client,
+        amount: Decimal::deserialize(amount),
+        state,
+    }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use crate::Transaction;
+    use crate::transaction::TransactionType::*;
+
+    use super::*;
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    pub fn temp_path(name: &str) -> PathBuf {
+        let id = COUNTER.fetch_add(1, Ordering::SeqCst);
+        env::temp_dir().join(format!("transaction-engine-{}-{}-{}", name, process::id(), id))
+    }
+
+    // The file is removed right away, and only lives as long as the store
+    pub fn temp_store() -> FileTransactionStore {
+        let path = temp_path("transactions");
+        let store = FileTransactionStore::open(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        store
+    }
+
+    fn withdrawal(client: u16, amount: Decimal) -> DisputableTransaction {
+        DisputableTransaction::new(&Transaction::new(Withdrawal, client, 1, Some(amount)), amount)
+    }
+
+    #[test]
+    fn test_record_and_lookup() {
+        let mut store = temp_store();
+        let mut transaction = withdrawal(12, dec!(3.1234));
+        transaction.state = TransactionState::Disputed;
+
+        store.record(7, transaction).unwrap();
+
+        assert_that!(store.lookup(7).unwrap(), is(equal_to(Some(transaction))));
+        assert_that!(store.lookup(6).unwrap(), is(equal_to(None)));
+        assert_that!(store.lookup(8).unwrap(), is(equal_to(None)));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut store = temp_store();
+        store.record(1, withdrawal(12, dec!(10))).unwrap();
+
+        store.remove(1).unwrap();
+        store.remove(2).unwrap();
+
+        assert_that!(store.lookup(1).unwrap(), is(equal_to(None)));
+    }
+
+    #[test]
+    fn test_all() {
+        let mut store = temp_store();
+        let transaction = withdrawal(12, dec!(10));
+        store.record(5, transaction).unwrap();
+        store.record(2, transaction).unwrap();
+
+        assert_that!(store.all().unwrap(), is(equal_to(vec![(2, transaction), (5, transaction)])));
+    }
+
+    #[test]
+    fn test_reopen() {
+        let path = temp_path("reopen");
+        let transaction = withdrawal(12, dec!(10));
+
+        FileTransactionStore::open(&path).unwrap().record(3, transaction).unwrap();
+        let store = FileTransactionStore::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_that!(store.lookup(3).unwrap(), is(equal_to(Some(transaction))));
+    }
+
+    #[test]
+    fn test_corrupted_record() {
+        let path = temp_path("corrupted");
+        std::fs::write(&path, [7; RECORD_SIZE as usize]).unwrap();
+        let store = FileTransactionStore::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_that!(store.lookup(0), is(equal_to(Err(StoreError::Corrupted))));
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use serde::{Serialize, Serializer};
+
+use crate::{Account, Transaction};
+use crate::error::ErrorKind;
+use crate::precision::serialize_balance;
+use crate::transaction::TransactionType;
+
+// An analyzed transaction, with the balances of its account right after it
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct HistoryEntry {
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<Decimal>,
+    #[serde(serialize_with = "serialize_balance")]
+    pub available: Decimal,
+    #[serde(serialize_with = "serialize_balance")]
+    pub held: Decimal,
+    #[serde(serialize_with = "serialize_balance")]
+    pub total: Decimal,
+    pub locked: bool,
+    // Why the transaction has been rejected, if it was
+    #[serde(serialize_with = "serialize_error")]
+    pub error: Option<ErrorKind>,
+}
+
+impl HistoryEntry {
+    pub fn new(transaction: &Transaction, account: &Account, error: Option<ErrorKind>) -> Self {
+        Self {
+            transaction_type: transaction.transaction_type,
+            client: transaction.client,
+            tx: transaction.tx,
+            amount: transaction.amount,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+            error,
+        }
+    }
+}
+
+fn serialize_error<S: Serializer>(error: &Option<ErrorKind>, serializer: S) -> Result<S::Ok, S::Error> {
+    match error {
+        Some(error) => serializer.collect_str(error),
+        None => serializer.serialize_none(),
+    }
+}
+
+// Every analyzed transaction, in order, grouped by client
+#[derive(Debug, Default)]
+pub struct History {
+    entries: HashMap<u16, Vec<HistoryEntry>>,
+    // The only client that is kept, when not all of them are
+    client: Option<u16>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Keeps the transactions of a single client, and ignores the others
+    pub fn of_client(client: u16) -> Self {
+        Self { entries: HashMap::new(), client: Some(client) }
+    }
+
+    pub fn keeps(&self, client: u16) -> bool {
+        self.client.is_none_or(|kept| kept == client)
+    }
+
+    pub fn record(&mut self, entry: HistoryEntry) {
+        if self.keeps(entry.client) {
+            self.entries.entry(entry.client).or_default().push(entry);
+        }
+    }
+
+    pub fn of(&self, client: u16) -> &[HistoryEntry] {
+        self.entries.get(&client).map_or(&[], |entries| entries.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use crate::transaction::TransactionType::*;
+
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut history = History::new();
+        let account = Account::new(1, dec!(10), dec!(0), false);
+        history.record(HistoryEntry::new(&Transaction::new(Deposit, 1, 1, Some(dec!(10))), &account, None));
+        history.record(HistoryEntry::new(&Transaction::new(Withdrawal, 1, 2, Some(dec!(20))), &account, Some(ErrorKind::InsufficientAvailableFunds)));
+
+        assert_that!(history.of(1).len(), is(equal_to(2)));
+        assert_that!(history.of(1)[1].error, is(equal_to(Some(ErrorKind::InsufficientAvailableFunds))));
+        assert_that!(history.of(2).len(), is(equal_to(0)));
+    }
+
+    #[test]
+    fn test_record_single_client() {
+        let mut history = History::of_client(2);
+        history.record(HistoryEntry::new(&Transaction::new(Deposit, 1, 1, Some(dec!(10))), &Account::empty(1), None));
+        history.record(HistoryEntry::new(&Transaction::new(Deposit, 2, 2, Some(dec!(10))), &Account::empty(2), None));
+
+        assert_that!(history.of(1).len(), is(equal_to(0)));
+        assert_that!(history.of(2).len(), is(equal_to(1)));
+    }
+
+    #[test]
+    fn test_serialize() {
+        let account = Account::new(1, dec!(10), dec!(0), false);
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(HistoryEntry::new(&Transaction::new(Deposit, 1, 1, Some(dec!(10))), &account, None)).unwrap();
+        writer.serialize(HistoryEntry::new(&Transaction::new(Dispute, 1, 3, None), &account, Some(ErrorKind::UnknownTransaction))).unwrap();
+
+        assert_that!(String::from_utf8(writer.into_inner().unwrap()).unwrap().as_str(), is(equal_to("\
+type,client,tx,amount,available,held,total,locked,error
+deposit,1,1,10,10.0000,0.0000,10.0000,false,
+dispute,1,3,,10.0000,0.0000,10.0000,false,Unknown transaction
+")));
+    }
+}
@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::{fmt, io};
+
+use csv::{ErrorKind, ReaderBuilder, Trim};
+
+use crate::engine::Engine;
+use crate::error::EngineError;
+use crate::precision::Precision;
+use crate::Transaction;
+
+// What stops the analysis of an input
+#[derive(Debug)]
+pub enum InputError {
+    Csv(csv::Error),
+    // An engine error that cannot be reported like a rejection, e.g. a journal that can't be written
+    Fatal(EngineError),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::Csv(err) => write!(f, "Could not read input: {}", err),
+            InputError::Fatal(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for InputError {}
+
+impl From<csv::Error> for InputError {
+    fn from(err: csv::Error) -> Self {
+        InputError::Csv(err)
+    }
+}
+
+// Analyzes the transactions of a CSV input, skipping the first `skip` ones, e.g. the ones already replayed from a journal.
+// Records that cannot be parsed and rejected transactions are passed to `on_error`, while failing to read the input
+// or a fatal engine error stops the analysis.
+pub fn analyze_reader<R, E, F>(engine: &mut E, reader: R, precision: Precision, skip: &mut usize, on_error: F) -> Result<(), InputError>
+    where R: io::Read, E: Engine, F: FnMut(&dyn Error) {
+    // Shared by the parsing and the analysis, which never report at the same time
+    let on_error = RefCell::new(on_error);
+    let fatal = RefCell::new(None);
+
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_reader(reader);
+
+    // Records are analyzed as soon as they are read, and the ones that cannot be parsed are reported and skipped
+    let mut read_error = None;
+    let transactions = reader.deserialize::<Transaction>().map_while(|result| match result {
+        Ok(transaction) => Some(Some(transaction)),
+        Err(err) => match err.kind() {
+            ErrorKind::Deserialize { .. } => {
+                (on_error.borrow_mut())(&err);
+                Some(None)
+            }
+            _ => {
+                read_error = Some(err);
+                None
+            }
+        }
+    }).flatten().filter_map(|transaction| match transaction.with_precision(precision) {
+        Ok(transaction) => Some(transaction),
+        Err(err) => {
+            (on_error.borrow_mut())(&err);
+            None
+        }
+    }).filter(|_| match *skip {
+        0 => true,
+        _ => {
+            *skip -= 1;
+            false
+        }
+    }).take_while(|_| fatal.borrow().is_none());
+
+    // Errors are reported as they happen, so that invalid transactions don't need to be kept around
+    engine.analyze_with(transactions, |error| match error.kind.is_fatal() {
+        true => {
+            fatal.borrow_mut().get_or_insert(error);
+        }
+        false => (on_error.borrow_mut())(&error),
+    });
+
+    if let Some(err) = fatal.into_inner() {
+        return Err(InputError::Fatal(err));
+    }
+
+    match read_error {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::Account;
+    use crate::engine_simple::EngineSimple;
+    use crate::journal::Journal;
+    use crate::validation::AmountLimits;
+
+    use super::*;
+
+    fn analyze(engine: &mut EngineSimple, input: &[u8], skip: &mut usize) -> (Result<(), InputError>, Vec<String>) {
+        let mut errors = vec![];
+        let result = analyze_reader(engine, input, Precision::default(), skip, |err| errors.push(err.to_string()));
+
+        (result, errors)
+    }
+
+    #[test]
+    fn test_reports_and_skips() {
+        let mut engine = EngineSimple::default();
+        let mut skip = 1;
+
+        let (result, errors) = analyze(&mut engine, b"type,client,tx,amount\n\
+            deposit,1,1,1\n\
+            deposit,1,2,2\n\
+            unknown,1,3,3\n\
+            deposit,1,4,0.00001\n\
+            withdrawal,1,5,5\n", &mut skip);
+
+        assert_that!(result.is_ok(), is(true));
+        assert_that!(errors.len(), is(equal_to(3)));
+        assert_that!(skip, is(equal_to(0)));
+        assert_that!(engine.accounts().unwrap(), is(equal_to(vec![Account::new(1, dec!(2), dec!(0), false)])));
+    }
+
+    // Writing to /dev/full fails as on a full disk
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_journal_failure_stops_analysis() {
+        let mut engine = EngineSimple::default().with_journal(Journal::open("/dev/full").unwrap());
+        let mut skip = 0;
+
+        let (result, errors) = analyze(&mut engine, b"type,client,tx,amount\n\
+            deposit,1,1,1\n\
+            withdrawal,1,2,5\n", &mut skip);
+
+        assert_that!(matches!(result, Err(InputError::Fatal(EngineError { kind: crate::error::ErrorKind::Journal(_), tx: 1, .. }))), is(true));
+        assert_that!(errors.len(), is(equal_to(0)));
+        assert_that!(engine.accounts().unwrap(), is(equal_to(vec![Account::empty(1)])));
+    }
+
+    mod properties {
+        use proptest::prelude::*;
+
+        use super::*;
+
+        // Without limits, so that huge amounts reach the balances
+        fn engine() -> EngineSimple {
+            EngineSimple::default()
+                .with_amount_limits(AmountLimits { max_deposit: Decimal::MAX, max_withdrawal: Decimal::MAX })
+                .with_ledger()
+                .with_history()
+        }
+
+        fn check(engine: &EngineSimple) -> Result<(), TestCaseError> {
+            prop_assert_eq!(engine.check_invariants().unwrap(), vec![]);
+            prop_assert_eq!(engine.verify_ledger(), Ok(()));
+
+            Ok(())
+        }
+
+        fn row() -> impl Strategy<Value=String> {
+            let transaction_type = prop_oneof![
+                Just("deposit"), Just("withdrawal"), Just("dispute"), Just("resolve"), Just("chargeback"), Just("unknown"),
+            ];
+            let amount = prop_oneof![
+                Just(String::new()),
+                (1..1_000_000i64).prop_map(|amount| Decimal::new(amount, 4).to_string()),
+                Just(Decimal::MAX.to_string()),
+                Just("-1".to_string()),
+                "[0-9.e-]{0,30}",
+            ];
+
+            (transaction_type, 0..4u16, 0..20u32, amount)
+                .prop_map(|(transaction_type, client, tx, amount)| format!("{},{},{},{}", transaction_type, client, tx, amount))
+        }
+
+        proptest! {
+            #[test]
+            fn test_arbitrary_bytes(input in proptest::collection::vec(any::<u8>(), 0..1024)) {
+                let mut engine = engine();
+
+                let _ = analyze_reader(&mut engine, input.as_slice(), Precision::default(), &mut 0, |_| {});
+
+                check(&engine)?;
+            }
+
+            #[test]
+            fn test_arbitrary_rows(rows in proptest::collection::vec(row(), 0..100)) {
+                let mut engine = engine();
+                let input = format!("type,client,tx,amount\n{}\n", rows.join("\n"));
+
+                let _ = analyze_reader(&mut engine, input.as_bytes(), Precision::default(), &mut 0, |_| {});
+
+                check(&engine)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::Account;
+use crate::store::{AccountStore, StoreError, TransactionStore};
+use crate::transaction_state::TransactionState;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InvariantViolation {
+    // Total is not the sum of available and held funds
+    TotalMismatch(u16),
+    NegativeHeld(u16),
+    // Held funds differ from the amounts of the open disputes
+    HeldMismatch { client: u16, held: Decimal, open_disputes: Decimal },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::TotalMismatch(client) =>
+                write!(f, "Total of client \"{}\" is not the sum of available and held funds", client),
+            InvariantViolation::NegativeHeld(client) => write!(f, "Held funds of client \"{}\" are negative", client),
+            InvariantViolation::HeldMismatch { client, held, open_disputes } =>
+                write!(f, "Held funds {} of client \"{}\" do not match open disputes of {}", held, client, open_disputes),
+        }
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+pub fn check_account(account: &Account, open_disputes: Decimal) -> Result<(), InvariantViolation> {
+    if Some(account.total) != account.available.checked_add(account.held) {
+        return Err(InvariantViolation::TotalMismatch(account.client));
+    }
+
+    if account.held < Decimal::ZERO {
+        return Err(InvariantViolation::NegativeHeld(account.client));
+    }
+
+    if account.held != open_disputes {
+        return Err(InvariantViolation::HeldMismatch { client: account.client, held: account.held, open_disputes });
+    }
+
+    Ok(())
+}
+
+// Returns all the violations, e.g. over the whole `AccountRepository`
+pub fn check_accounts<A: AccountStore>(accounts: &A, open_disputes: &HashMap<u16, Decimal>) -> Result<Vec<InvariantViolation>, StoreError> {
+    Ok(accounts.all()?.iter()
+        .filter_map(|account| check_account(account, open_disputes.get(&account.client).copied().unwrap_or_default()).err())
+        .collect())
+}
+
+// Sum of the amounts of the disputed transactions of every client
+pub fn open_disputes<T: TransactionStore>(transactions: &T) -> Result<HashMap<u16, Decimal>, StoreError> {
+    let mut open_disputes = HashMap::new();
+
+    for (_, transaction) in transactions.all()? {
+        if transaction.state == TransactionState::Disputed {
+            let sum: &mut Decimal = open_disputes.entry(transaction.client).or_default();
+            *sum = sum.checked_add(transaction.amount).ok_or(StoreError::Corrupted)?;
+        }
+    }
+
+    Ok(open_disputes)
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use crate::account_repository::AccountRepository;
+    use crate::Transaction;
+    use crate::transaction::DisputableTransaction;
+    use crate::transaction::TransactionType::*;
+
+    use super::*;
+
+    #[test]
+    fn test_valid_account() {
+        assert_that!(check_account(&Account::new(1, dec!(10), dec!(5), false), dec!(5)), is(equal_to(Ok(()))));
+    }
+
+    #[test]
+    fn test_total_mismatch() {
+        let mut account = Account::new(1, dec!(10), dec!(0), false);
+        account.total = dec!(11);
+
+        assert_that!(check_account(&account, dec!(0)), is(equal_to(Err(InvariantViolation::TotalMismatch(1)))));
+    }
+
+    #[test]
+    fn test_negative_held() {
+        let account = Account::new(1, dec!(10), dec!(-1), false);
+
+        assert_that!(check_account(&account, dec!(-1)), is(equal_to(Err(InvariantViolation::NegativeHeld(1)))));
+    }
+
+    #[test]
+    fn test_repository() {
+        let mut repository = AccountRepository::new();
+        repository.update(Account::new(1, dec!(10), dec!(5), false)).unwrap();
+        repository.update(Account::new(2, dec!(10), dec!(0), false)).unwrap();
+        let mut transactions = HashMap::new();
+        let mut disputed = DisputableTransaction::new(&Transaction::new(Deposit, 1, 1, None), dec!(3));
+        disputed.state = TransactionState::Disputed;
+        transactions.record(1, disputed).unwrap();
+
+        let violations = check_accounts(&repository, &open_disputes(&transactions).unwrap()).unwrap();
+
+        assert_that!(violations, is(equal_to(vec![
+            InvariantViolation::HeldMismatch { client: 1, held: dec!(5), open_disputes: dec!(3) },
+        ])));
+    }
+}
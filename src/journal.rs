@@ -0,0 +1,238 @@
+use std::{fmt, io};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use csv::ReaderBuilder;
+use rust_decimal::Decimal;
+
+use crate::{Account, Transaction};
+use crate::store::StoreError;
+use crate::transaction::TransactionType;
+
+// How much an accepted transaction changed the balances of its account
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BalanceDelta {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+}
+
+impl BalanceDelta {
+    pub fn between(before: &Account, after: &Account) -> Self {
+        Self {
+            available: after.available - before.available,
+            held: after.held - before.held,
+            total: after.total - before.total,
+        }
+    }
+}
+
+// Rejected transactions don't have a delta, but are journaled anyway: they can still use up
+// a transaction id, and they tell how far in the input the engine got
+#[derive(Debug)]
+pub struct JournalEntry {
+    pub transaction: Transaction,
+    pub delta: Option<BalanceDelta>,
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(io::Error),
+    Csv(csv::Error),
+    Store(StoreError),
+    // Replaying the entry at the given position did not give the same result as when it was journaled
+    Diverged(usize),
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::Io(err) => write!(f, "Could not access journal: {}", err),
+            JournalError::Csv(err) => write!(f, "Could not read journal: {}", err),
+            JournalError::Store(err) => write!(f, "Store failure: {}", err),
+            JournalError::Diverged(position) => write!(f, "Journal entry {} could not be replayed with the same result", position),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+impl From<io::Error> for JournalError {
+    fn from(err: io::Error) -> Self {
+        JournalError::Io(err)
+    }
+}
+
+impl From<csv::Error> for JournalError {
+    fn from(err: csv::Error) -> Self {
+        JournalError::Csv(err)
+    }
+}
+
+impl From<StoreError> for JournalError {
+    fn from(err: StoreError) -> Self {
+        JournalError::Store(err)
+    }
+}
+
+// Type, client, tx, amount, and the balance delta
+type Record = (TransactionType, u16, u32, Option<Decimal>, Option<Decimal>, Option<Decimal>, Option<Decimal>);
+
+// Append-only file with one line per analyzed transaction: `<type>,<client>,<tx>,<amount>,<available>,<held>,<total>`,
+// where the last three fields are the balance delta, empty for rejected transactions.
+// Every entry is written with a single call and synced before the stores are updated, so a crash can at most
+// leave an incomplete last line, which is dropped on recovery.
+pub struct Journal {
+    file: File,
+    // Length of the file before the last entry was appended
+    last_length: Option<u64>,
+}
+
+impl Journal {
+    // Opens an existing journal, or creates an empty one
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+
+        Ok(Self { file, last_length: None })
+    }
+
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<(), StoreError> {
+        let transaction = &entry.transaction;
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+        let delta = entry.delta.map_or((None, None, None), |delta| (Some(delta.available), Some(delta.held), Some(delta.total)));
+
+        writer.serialize((transaction.transaction_type, transaction.client, transaction.tx, transaction.amount, delta.0, delta.1, delta.2))
+            .map_err(|_| StoreError::Corrupted)?;
+        let line = writer.into_inner().map_err(|_| StoreError::Corrupted)?;
+
+        self.last_length = Some(self.file.metadata()?.len());
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+
+    // Drops the last appended entry, e.g. when the stores could not be updated after all
+    pub fn rollback(&mut self) -> Result<(), StoreError> {
+        if let Some(length) = self.last_length.take() {
+            self.file.set_len(length)?;
+            self.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    // Drops all the entries, once the run they belong to has completed
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.last_length = None;
+        self.file.set_len(0)?;
+        self.file.sync_data()
+    }
+
+    // Reads all the complete entries, and drops an incomplete last one
+    pub fn entries(&mut self) -> Result<Vec<JournalEntry>, JournalError> {
+        let complete = self.complete_length()?;
+        self.file.set_len(complete)?;
+        self.file.seek(SeekFrom::Start(0))?;
+
+        let mut reader = ReaderBuilder::new().has_headers(false).from_reader(BufReader::new(&self.file).take(complete));
+        let mut entries = vec![];
+
+        for record in reader.deserialize() {
+            let (transaction_type, client, tx, amount, available, held, total): Record = record?;
+            let delta = match (available, held, total) {
+                (Some(available), Some(held), Some(total)) => Some(BalanceDelta { available, held, total }),
+                _ => None,
+            };
+
+            entries.push(JournalEntry { transaction: Transaction { transaction_type, client, tx, amount }, delta });
+        }
+
+        Ok(entries)
+    }
+
+    // Length of the file up to the end of its last complete line
+    fn complete_length(&mut self) -> io::Result<u64> {
+        self.file.seek(SeekFrom::Start(0))?;
+
+        let mut reader = BufReader::new(&self.file);
+        let mut line = vec![];
+        let mut length = 0;
+
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+
+            if read == 0 || line.last() != Some(&b'\n') {
+                return Ok(length);
+            }
+
+            length += read as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use crate::file_transaction_store::tests::temp_path;
+    use crate::transaction::TransactionType::*;
+
+    use super::*;
+
+    fn entry(transaction: Transaction, delta: Option<BalanceDelta>) -> JournalEntry {
+        JournalEntry { transaction, delta }
+    }
+
+    #[test]
+    fn test_append_and_read() {
+        let path = temp_path("journal");
+        let delta = BalanceDelta { available: dec!(-1.5), held: dec!(1.5), total: dec!(0) };
+
+        let mut journal = Journal::open(&path).unwrap();
+        journal.append(&entry(Transaction::new(Dispute, 1, 2, None), Some(delta))).unwrap();
+        journal.append(&entry(Transaction::new(Withdrawal, 1, 3, Some(dec!(10))), None)).unwrap();
+
+        let entries = Journal::open(&path).unwrap().entries().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_that!(entries.len(), is(equal_to(2)));
+        assert_that!(entries[0].transaction.transaction_type, is(equal_to(Dispute)));
+        assert_that!(entries[0].transaction.amount, is(equal_to(None)));
+        assert_that!(entries[0].delta, is(equal_to(Some(delta))));
+        assert_that!(entries[1].transaction.amount, is(equal_to(Some(dec!(10)))));
+        assert_that!(entries[1].delta, is(equal_to(None)));
+    }
+
+    #[test]
+    fn test_clear() {
+        let path = temp_path("journal");
+
+        let mut journal = Journal::open(&path).unwrap();
+        journal.append(&entry(Transaction::new(Deposit, 1, 1, Some(dec!(10))), None)).unwrap();
+        journal.clear().unwrap();
+        journal.append(&entry(Transaction::new(Deposit, 1, 2, Some(dec!(5))), None)).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_that!(content.as_str(), is(equal_to("deposit,1,2,5,,,\n")));
+    }
+
+    #[test]
+    fn test_drop_incomplete_entry() {
+        let path = temp_path("journal");
+        std::fs::write(&path, "deposit,1,1,10,10,0,10\ndeposit,1,2,1").unwrap();
+
+        let mut journal = Journal::open(&path).unwrap();
+        let entries = journal.entries().unwrap();
+        journal.append(&entry(Transaction::new(Deposit, 1, 3, Some(dec!(5))), None)).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_that!(entries.len(), is(equal_to(1)));
+        assert_that!(content.as_str(), is(equal_to("deposit,1,1,10,10,0,10\ndeposit,1,3,5,,,\n")));
+    }
+}
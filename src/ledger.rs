@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::{fmt, io};
+
+use rust_decimal::Decimal;
+
+use crate::Account;
+use crate::error::ErrorKind;
+use crate::store::StoreError;
+use crate::transaction::TransactionType;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum LedgerAccount {
+    ClientAvailable(u16),
+    ClientHeld(u16),
+    // Where deposits come from and withdrawals go to
+    ExternalFunding,
+    // Where charged back deposits go to
+    ChargebackLoss,
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAccount::ClientAvailable(client) => write!(f, "available:{}", client),
+            LedgerAccount::ClientHeld(client) => write!(f, "held:{}", client),
+            LedgerAccount::ExternalFunding => write!(f, "external"),
+            LedgerAccount::ChargebackLoss => write!(f, "chargeback_loss"),
+        }
+    }
+}
+
+// Moves an amount from the debited account to the credited one, so every entry is balanced.
+// Opening balances have no transaction id.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LedgerEntry {
+    pub tx: Option<u32>,
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub amount: Decimal,
+}
+
+impl LedgerEntry {
+    // The operation is applied to an original deposit or withdrawal, which is the operation itself
+    // for deposits and withdrawals
+    pub fn for_operation(tx: u32, client: u16, operation: TransactionType, original: TransactionType, amount: Decimal) -> Self {
+        use LedgerAccount::*;
+        use TransactionType::*;
+
+        let (debit, credit) = match (operation, original) {
+            (Deposit, _) => (ExternalFunding, ClientAvailable(client)),
+            (Withdrawal, _) => (ClientAvailable(client), ExternalFunding),
+            (Dispute, Withdrawal) => (ExternalFunding, ClientHeld(client)),
+            (Dispute, _) => (ClientAvailable(client), ClientHeld(client)),
+            (Resolve, Withdrawal) => (ClientHeld(client), ExternalFunding),
+            (Resolve, _) => (ClientHeld(client), ClientAvailable(client)),
+            (Chargeback, Withdrawal) => (ClientHeld(client), ClientAvailable(client)),
+            (Chargeback, _) => (ClientHeld(client), ChargebackLoss),
+        };
+
+        Self { tx: Some(tx), debit, credit, amount }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LedgerError {
+    // The balances of the client account differ from the ones in the ledger
+    Mismatch(u16),
+    // A ledger account would overflow
+    Overflow,
+    Store(StoreError),
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::Mismatch(client) => write!(f, "Balances of client \"{}\" do not match the ledger", client),
+            LedgerError::Overflow => write!(f, "Ledger balance would overflow"),
+            LedgerError::Store(err) => write!(f, "Store failure: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
+impl From<StoreError> for LedgerError {
+    fn from(err: StoreError) -> Self {
+        LedgerError::Store(err)
+    }
+}
+
+// Keeps the resulting balance of every ledger account, and writes every entry to the given writer (if any)
+// as a trail of why balances changed: `tx,debit,credit,amount`, with an empty id for opening balances.
+// A balance is the sum of the credits minus the sum of the debits, so all the balances always add up to zero.
+#[derive(Default)]
+pub struct Ledger {
+    balances: HashMap<LedgerAccount, Decimal>,
+    writer: Option<csv::Writer<Box<dyn io::Write + Send>>>,
+    posted: usize,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn writing_to<W: io::Write + Send + 'static>(writer: W) -> Self {
+        let writer: Box<dyn io::Write + Send> = Box::new(writer);
+
+        Self { writer: Some(csv::WriterBuilder::new().has_headers(false).from_writer(writer)), ..Self::default() }
+    }
+
+    // Starts again from the current balances of the given accounts, funded externally.
+    // Opening entries are written again as well, so the last ones written are the ones that count.
+    pub fn open(&mut self, accounts: &[Account]) -> Result<(), LedgerError> {
+        self.balances.clear();
+
+        for account in accounts {
+            for (credit, amount) in [(LedgerAccount::ClientAvailable(account.client), account.available),
+                (LedgerAccount::ClientHeld(account.client), account.held)] {
+                if !amount.is_zero() {
+                    self.post(LedgerEntry { tx: None, debit: LedgerAccount::ExternalFunding, credit, amount })
+                        .map_err(|err| match err {
+                            ErrorKind::Ledger(err) => LedgerError::Store(err),
+                            _ => LedgerError::Overflow,
+                        })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Nothing is posted if either balance would overflow. The balances are updated even if the entry
+    // cannot be written, since they must keep matching the accounts.
+    pub fn post(&mut self, entry: LedgerEntry) -> Result<(), ErrorKind> {
+        let (debit, credit) = self.posted_balances(&entry)?;
+
+        self.balances.insert(entry.debit, debit);
+        self.balances.insert(entry.credit, credit);
+        self.posted += 1;
+
+        if let Some(writer) = &mut self.writer {
+            writer.serialize((entry.tx, entry.debit.to_string(), entry.credit.to_string(), entry.amount))
+                .map_err(|err| ErrorKind::Ledger(write_error(err)))?;
+        }
+
+        Ok(())
+    }
+
+    // Entries are buffered, so they are only all written once flushed
+    pub fn flush(&mut self) -> Result<(), StoreError> {
+        match &mut self.writer {
+            Some(writer) => Ok(writer.flush()?),
+            None => Ok(()),
+        }
+    }
+
+    // Whether the entry can be posted, without posting it
+    pub fn check(&self, entry: &LedgerEntry) -> Result<(), ErrorKind> {
+        self.posted_balances(entry).map(|_| ())
+    }
+
+    // Balances of the debited and credited accounts, once the entry is posted
+    fn posted_balances(&self, entry: &LedgerEntry) -> Result<(Decimal, Decimal), ErrorKind> {
+        let debit = self.balance(entry.debit).checked_sub(entry.amount).ok_or(ErrorKind::Overflow)?;
+        let credit = self.balance(entry.credit).checked_add(entry.amount).ok_or(ErrorKind::Overflow)?;
+
+        Ok((debit, credit))
+    }
+
+    // How many entries have been posted, including the opening ones
+    pub fn posted(&self) -> usize {
+        self.posted
+    }
+
+    pub fn balance(&self, account: LedgerAccount) -> Decimal {
+        self.balances.get(&account).copied().unwrap_or_default()
+    }
+
+    pub fn verify(&self, account: &Account) -> Result<(), LedgerError> {
+        let available = self.balance(LedgerAccount::ClientAvailable(account.client));
+        let held = self.balance(LedgerAccount::ClientHeld(account.client));
+
+        if account.available != available || account.held != held || Some(account.total) != available.checked_add(held) {
+            return Err(LedgerError::Mismatch(account.client));
+        }
+
+        Ok(())
+    }
+}
+
+fn write_error(err: csv::Error) -> StoreError {
+    match err.into_kind() {
+        csv::ErrorKind::Io(err) => StoreError::from(err),
+        _ => StoreError::Corrupted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use super::LedgerAccount::*;
+    use crate::file_transaction_store::tests::temp_path;
+    use crate::transaction::TransactionType::*;
+
+    fn post(ledger: &mut Ledger, operation: TransactionType, original: TransactionType, amount: Decimal) {
+        ledger.post(LedgerEntry::for_operation(1, 1, operation, original, amount)).unwrap();
+    }
+
+    fn total(ledger: &Ledger) -> Decimal {
+        ledger.balances.values().sum()
+    }
+
+    #[test]
+    fn test_deposit_chargeback() {
+        let mut ledger = Ledger::new();
+        post(&mut ledger, Deposit, Deposit, dec!(10));
+        post(&mut ledger, Dispute, Deposit, dec!(10));
+        post(&mut ledger, Chargeback, Deposit, dec!(10));
+
+        assert_that!(ledger.balance(ClientAvailable(1)), is(equal_to(dec!(0))));
+        assert_that!(ledger.balance(ClientHeld(1)), is(equal_to(dec!(0))));
+        assert_that!(ledger.balance(ExternalFunding), is(equal_to(dec!(-10))));
+        assert_that!(ledger.balance(ChargebackLoss), is(equal_to(dec!(10))));
+        assert_that!(total(&ledger), is(equal_to(dec!(0))));
+        assert_that!(ledger.posted(), is(equal_to(3)));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute() {
+        let mut ledger = Ledger::new();
+        post(&mut ledger, Deposit, Deposit, dec!(10));
+        post(&mut ledger, Withdrawal, Withdrawal, dec!(4));
+        post(&mut ledger, Dispute, Withdrawal, dec!(4));
+
+        assert_that!(ledger.verify(&Account::new(1, dec!(6), dec!(4), false)), is(equal_to(Ok(()))));
+        assert_that!(ledger.balance(ExternalFunding), is(equal_to(dec!(-10))));
+        assert_that!(total(&ledger), is(equal_to(dec!(0))));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut ledger = Ledger::new();
+        post(&mut ledger, Deposit, Deposit, Decimal::MAX);
+
+        let result = ledger.post(LedgerEntry::for_operation(2, 2, Deposit, Deposit, dec!(1)));
+
+        assert_that!(result, is(equal_to(Err(ErrorKind::Overflow))));
+        assert_that!(ledger.balance(ClientAvailable(2)), is(equal_to(dec!(0))));
+        assert_that!(ledger.posted(), is(equal_to(1)));
+    }
+
+    #[test]
+    fn test_verify_mismatch() {
+        let mut ledger = Ledger::new();
+        ledger.open(&[Account::new(1, dec!(5), dec!(1), false)]).unwrap();
+        post(&mut ledger, Resolve, Deposit, dec!(1));
+
+        assert_that!(ledger.verify(&Account::new(1, dec!(6), dec!(0), false)), is(equal_to(Ok(()))));
+        assert_that!(ledger.verify(&Account::new(1, dec!(5), dec!(1), false)), is(equal_to(Err(LedgerError::Mismatch(1)))));
+    }
+
+    #[test]
+    fn test_write_entries() {
+        let path = temp_path("ledger");
+        let mut ledger = Ledger::writing_to(std::fs::File::create(&path).unwrap());
+        ledger.open(&[Account::new(1, dec!(5), dec!(0), false)]).unwrap();
+        post(&mut ledger, Withdrawal, Withdrawal, dec!(1.5));
+        ledger.flush().unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_that!(written.as_str(), is(equal_to(",external,available:1,5\n1,available:1,external,1.5\n")));
+        assert_that!(ledger.posted(), is(equal_to(2)));
+    }
+}
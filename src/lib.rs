@@ -0,0 +1,28 @@
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic))]
+
+pub use crate::account::Account;
+pub use crate::transaction::Transaction;
+
+pub mod account;
+pub mod transaction;
+pub mod transaction_state;
+pub mod engine;
+pub mod error;
+pub mod seen_transactions;
+
+pub mod store;
+pub mod engine_simple;
+pub mod engine_extended;
+pub mod engine_sharded;
+pub mod account_repository;
+pub mod cached_amounts;
+pub mod file_transaction_store;
+pub mod snapshot;
+pub mod opening_balances;
+pub mod journal;
+pub mod ledger;
+pub mod history;
+pub mod invariants;
+pub mod precision;
+pub mod validation;
+pub mod input;
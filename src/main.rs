@@ -1,82 +1,175 @@
 use std::{env, io};
 use std::error::Error;
-use std::ffi::OsString;
-use std::fs::File;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
 use std::process;
+use std::str::FromStr;
 
-use csv::{ReaderBuilder, Trim};
-
-use crate::account::Account;
 #[cfg(feature = "extended")]
-use crate::account_repository::AccountRepository;
+use transaction_engine::account_repository::AccountRepository;
 #[cfg(feature = "extended")]
-use crate::cached_amounts::CachedAmounts;
-use crate::engine::Engine;
+use transaction_engine::cached_amounts::CachedAmounts;
+use transaction_engine::account::SortKey;
+use transaction_engine::engine::Engine;
+use transaction_engine::input::analyze_reader;
 #[cfg(feature = "extended")]
-use crate::engine_extended::EngineExtended;
+use transaction_engine::engine_extended::EngineExtended;
 #[cfg(feature = "simple")]
-use crate::engine_simple::EngineSimple;
-use crate::transaction::Transaction;
+use transaction_engine::engine_simple::EngineSimple;
+use transaction_engine::journal::Journal;
+use transaction_engine::precision::Precision;
+#[cfg(feature = "extended")]
+use transaction_engine::seen_transactions::TransactionBitmap;
+use transaction_engine::validation::AmountLimits;
+
+// Command line options, e.g. `--from-snapshot state.csv --save-snapshot state.csv transactions.csv`,
+// optionally preceded by a `history <client>` subcommand
+struct Options {
+    history: Option<u16>,
+    inputs: Vec<OsString>,
+    accounts: Option<OsString>,
+    journal: Option<OsString>,
+    ledger: Option<OsString>,
+    from_snapshot: Option<OsString>,
+    save_snapshot: Option<OsString>,
+    sort: Vec<SortKey>,
+    precision: Precision,
+    amount_limits: AmountLimits,
+}
 
-mod account;
-mod transaction;
-mod engine;
+fn run() -> Result<(), Box<dyn Error>> {
+    let options = parse_args(env::args_os().skip(1))?;
 
-#[cfg(feature = "simple")]
-mod engine_simple;
+    #[cfg(feature = "simple")]
+    let mut engine = EngineSimple::default();
 
-#[cfg(feature = "extended")]
-mod engine_extended;
+    #[cfg(feature = "extended")]
+    let mut engine = EngineExtended::new(AccountRepository::new(), CachedAmounts::new(), TransactionBitmap::new());
 
-#[cfg(feature = "extended")]
-mod account_repository;
+    engine = engine.with_amount_limits(options.amount_limits);
 
-#[cfg(feature = "extended")]
-mod cached_amounts;
+    if let Some(client) = options.history {
+        engine = engine.with_history_for(client);
+    }
 
-fn run() -> Result<(), Box<dyn Error>> {
-    let file_path = get_first_arg()?;
-    let file = File::open(file_path)?;
+    // Set up before loading any balance, which become the opening entries
+    if let Some(path) = &options.ledger {
+        engine = engine.with_ledger_writer(File::create(path)?);
+    }
 
-    let mut reader = ReaderBuilder::new()
-        .trim(Trim::All).from_reader(file);
+    // Only the new input files need to be analyzed, when starting from a previous state
+    if let Some(path) = &options.from_snapshot {
+        engine.restore_snapshot(File::open(path)?)?;
+    }
 
-    let mut transactions = vec![];
+    // Opening balances, e.g. from the output of the previous day
+    if let Some(path) = &options.accounts {
+        engine.load_accounts(File::open(path)?)?;
+    }
 
-    for result in reader.deserialize() {
-        let record: Transaction = result?;
-        transactions.push(record);
+    // After a crash, the journaled transactions are replayed instead of being analyzed again
+    let mut skip = 0;
+    if let Some(path) = &options.journal {
+        engine = engine.with_journal(Journal::open(path)?);
+        skip = engine.recover()?;
     }
 
-    #[cfg(feature = "simple")]
-    let mut engine = EngineSimple::new();
+    for path in &options.inputs {
+        analyze_file(&mut engine, path, options.precision, &mut skip)?;
+    }
 
-    #[cfg(feature = "extended")]
-    let mut engine = EngineExtended::new(AccountRepository::new(), CachedAmounts::new(), CachedAmounts::new());
+    engine.complete_ledger()?;
+
+    // The run is complete, so a later one with the same journal starts from scratch. This happens before saving
+    // the snapshot: a crash in between only means analyzing the same input again, from the same starting state.
+    engine.complete_journal()?;
 
-    // Returning a pair here so that we can handle valid transactions and report errors for invalid ones
-    let (accounts, errors) = engine.analyze(transactions);
+    // Written next to the previous snapshot, which is only replaced once the new one is on disk, so that a failure
+    // never loses the starting state
+    if let Some(path) = &options.save_snapshot {
+        let mut temporary = path.clone();
+        temporary.push(".tmp");
 
-    for error in errors {
-        eprintln!("{}", error)
+        let mut file = File::create(&temporary)?;
+        engine.save_snapshot(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
     }
 
     let mut writer = csv::Writer::from_writer(io::stdout());
 
-    for account in accounts {
+    // Shows what happened to a single client, instead of the final state of all the accounts
+    if let Some(client) = options.history {
+        for entry in engine.history(client) {
+            writer.serialize(entry)?;
+        }
+
+        return Ok(());
+    }
+
+    for account in engine.sorted_accounts(&options.sort)? {
         writer.serialize(account)?;
     }
 
     Ok(())
 }
 
-/// Returns the first positional argument sent to this process. If there are no
-/// positional arguments, then this returns an error.
-fn get_first_arg() -> Result<OsString, Box<dyn Error>> {
-    match env::args_os().nth(1) {
-        None => Err(From::from("expected 1 argument, but got none")),
-        Some(file_path) => Ok(file_path),
+fn analyze_file<E: Engine>(engine: &mut E, path: &OsStr, precision: Precision, skip: &mut usize) -> Result<(), Box<dyn Error>> {
+    analyze_reader(engine, File::open(path)?, precision, skip, |err| eprintln!("{}", err))?;
+
+    Ok(())
+}
+
+/// Parses the subcommand, the options and the input files, which are analyzed in the given order.
+/// Without input files, accounts or snapshot to start from, this returns an error.
+fn parse_args<I: Iterator<Item=OsString>>(args: I) -> Result<Options, Box<dyn Error>> {
+    let mut args = args.peekable();
+    let mut options = Options { history: None, inputs: vec![], accounts: None, journal: None, ledger: None, from_snapshot: None, save_snapshot: None, sort: vec![], precision: Precision::default(), amount_limits: AmountLimits::default() };
+
+    if args.peek().and_then(|arg| arg.to_str()) == Some("history") {
+        args.next();
+        let client = option_value(&mut args, "history")?;
+        let client = client.to_str().and_then(|client| client.parse().ok()).ok_or_else(|| format!("invalid client {:?}", client))?;
+        options.history = Some(client);
     }
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--accounts") => options.accounts = Some(option_value(&mut args, "--accounts")?),
+            Some("--journal") => options.journal = Some(option_value(&mut args, "--journal")?),
+            Some("--ledger") => options.ledger = Some(option_value(&mut args, "--ledger")?),
+            Some("--from-snapshot") => options.from_snapshot = Some(option_value(&mut args, "--from-snapshot")?),
+            Some("--save-snapshot") => options.save_snapshot = Some(option_value(&mut args, "--save-snapshot")?),
+            Some("--precision") => options.precision = options.precision.with_places(parse_value(&option_value(&mut args, "--precision")?)?)?,
+            Some("--rounding") => options.precision.policy = parse_value(&option_value(&mut args, "--rounding")?)?,
+            Some("--max-deposit") => options.amount_limits.max_deposit = parse_value(&option_value(&mut args, "--max-deposit")?)?,
+            Some("--max-withdrawal") => options.amount_limits.max_withdrawal = parse_value(&option_value(&mut args, "--max-withdrawal")?)?,
+            Some("--sort") => options.sort = parse_sort_keys(&option_value(&mut args, "--sort")?)?,
+            Some(option) if option.starts_with("--") => return Err(format!("unknown option {}", option).into()),
+            _ => options.inputs.push(arg),
+        }
+    }
+
+    if options.inputs.is_empty() && options.accounts.is_none() && options.from_snapshot.is_none() {
+        return Err(From::from("expected 1 argument, but got none"));
+    }
+
+    Ok(options)
+}
+
+// Comma separated, e.g. `total,locked`
+fn parse_sort_keys(keys: &OsStr) -> Result<Vec<SortKey>, Box<dyn Error>> {
+    let keys = keys.to_str().ok_or("invalid sort keys")?;
+
+    Ok(keys.split(',').map(|key| key.trim().parse()).collect::<Result<_, _>>()?)
+}
+
+fn parse_value<T>(value: &OsStr) -> Result<T, Box<dyn Error>> where T: FromStr, T::Err: Into<Box<dyn Error>> {
+    value.to_str().ok_or("invalid value")?.parse().map_err(Into::into)
+}
+
+fn option_value<I: Iterator<Item=OsString>>(args: &mut I, option: &str) -> Result<OsString, Box<dyn Error>> {
+    args.next().ok_or_else(|| format!("expected a value for {}", option).into())
 }
 
 fn main() {
@@ -0,0 +1,215 @@
+use std::{fmt, io};
+
+use csv::{ReaderBuilder, Trim};
+use rust_decimal::Decimal;
+
+use crate::Account;
+use crate::ledger::LedgerError;
+use crate::precision::{BALANCE_PLACES, fits_balance_places};
+use crate::store::{AccountStore, StoreError};
+
+// An account whose total doesn't match its available and held funds
+#[derive(Debug, PartialEq)]
+pub struct BalanceMismatch {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+}
+
+impl fmt::Display for BalanceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Total {} of client \"{}\" does not match available {} and held {}", self.total, self.client, self.available, self.held)
+    }
+}
+
+#[derive(Debug)]
+pub enum OpeningBalancesError {
+    Csv(csv::Error),
+    Mismatches(Vec<BalanceMismatch>),
+    // Clients with negative available or held funds
+    NegativeBalances(Vec<u16>),
+    // Clients with funds that cannot be written back without rounding
+    ExcessPrecision(Vec<u16>),
+    Store(StoreError),
+    Ledger(LedgerError),
+}
+
+impl fmt::Display for OpeningBalancesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpeningBalancesError::Csv(err) => write!(f, "Could not read opening balances: {}", err),
+            OpeningBalancesError::Mismatches(mismatches) => {
+                let lines: Vec<String> = mismatches.iter().map(|mismatch| mismatch.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            OpeningBalancesError::NegativeBalances(clients) => {
+                let lines: Vec<String> = clients.iter().map(|client| format!("Client \"{}\" has negative funds", client)).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            OpeningBalancesError::ExcessPrecision(clients) => {
+                let lines: Vec<String> = clients.iter()
+                    .map(|client| format!("Client \"{}\" has funds with more than {} decimal places", client, BALANCE_PLACES))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            OpeningBalancesError::Store(err) => write!(f, "Store failure: {}", err),
+            OpeningBalancesError::Ledger(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for OpeningBalancesError {}
+
+impl From<csv::Error> for OpeningBalancesError {
+    fn from(err: csv::Error) -> Self {
+        OpeningBalancesError::Csv(err)
+    }
+}
+
+impl From<StoreError> for OpeningBalancesError {
+    fn from(err: StoreError) -> Self {
+        OpeningBalancesError::Store(err)
+    }
+}
+
+impl From<LedgerError> for OpeningBalancesError {
+    fn from(err: LedgerError) -> Self {
+        OpeningBalancesError::Ledger(err)
+    }
+}
+
+// Reads accounts in the same format the engine outputs them (`client,available,held,total,locked`).
+// Nothing is stored unless every account is consistent, has no negative funds and no more decimal places than
+// the output has, and all the mismatches are reported at once.
+pub fn load_opening_balances<R, A>(reader: R, accounts: &mut A) -> Result<(), OpeningBalancesError>
+    where R: io::Read, A: AccountStore {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+    let mut loaded = vec![];
+    let mut mismatches = vec![];
+    let mut negative = vec![];
+    let mut excess_precision = vec![];
+
+    for account in reader.deserialize::<Account>() {
+        let account = account?;
+
+        if Some(account.total) != account.available.checked_add(account.held) {
+            mismatches.push(BalanceMismatch { client: account.client, available: account.available, held: account.held, total: account.total });
+        }
+
+        if account.available < Decimal::ZERO || account.held < Decimal::ZERO {
+            negative.push(account.client);
+        }
+
+        if ![account.available, account.held, account.total].iter().all(|balance| fits_balance_places(*balance)) {
+            excess_precision.push(account.client);
+        }
+
+        loaded.push(account);
+    }
+
+    if !mismatches.is_empty() {
+        return Err(OpeningBalancesError::Mismatches(mismatches));
+    }
+
+    if !negative.is_empty() {
+        return Err(OpeningBalancesError::NegativeBalances(negative));
+    }
+
+    if !excess_precision.is_empty() {
+        return Err(OpeningBalancesError::ExcessPrecision(excess_precision));
+    }
+
+    accounts.update_many(loaded)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let mut accounts = HashMap::new();
+        let data = "\
+client,available,held,total,locked
+1, 1.5, 0.5, 2.0, false
+2,0,0,0,true
+";
+
+        load_opening_balances(data.as_bytes(), &mut accounts).unwrap();
+
+        assert_that!(accounts.get(&1).cloned(), is(equal_to(Some(Account::new(1, dec!(1.5), dec!(0.5), false)))));
+        assert_that!(accounts.get(&2).cloned(), is(equal_to(Some(Account::new(2, dec!(0), dec!(0), true)))));
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let mut accounts = HashMap::new();
+        let data = "\
+client,available,held,total,locked
+1,1.5,0.5,2.5,false
+2,1,0,1,false
+3,1,1,1,false
+";
+
+        let err = load_opening_balances(data.as_bytes(), &mut accounts).unwrap_err();
+
+        assert_that!(err.to_string().as_str(), is(equal_to("\
+Total 2.5 of client \"1\" does not match available 1.5 and held 0.5
+Total 1 of client \"3\" does not match available 1 and held 1")));
+        assert_that!(accounts.is_empty(), is(true));
+    }
+
+    #[test]
+    fn test_negative_balances() {
+        let mut accounts = HashMap::new();
+        let data = "\
+client,available,held,total,locked
+1,10,-5,5,false
+2,1,0,1,false
+3,-1,2,1,false
+";
+
+        let err = load_opening_balances(data.as_bytes(), &mut accounts).unwrap_err();
+
+        assert_that!(err.to_string().as_str(), is(equal_to("\
+Client \"1\" has negative funds
+Client \"3\" has negative funds")));
+        assert_that!(accounts.is_empty(), is(true));
+    }
+
+    #[test]
+    fn test_excess_precision() {
+        let mut accounts = HashMap::new();
+        let data = "\
+client,available,held,total,locked
+1,1.5000,0.5,2.0,false
+2,0.00015,0.00015,0.0003,false
+";
+
+        let err = load_opening_balances(data.as_bytes(), &mut accounts).unwrap_err();
+
+        assert_that!(err.to_string().as_str(), is(equal_to("Client \"2\" has funds with more than 4 decimal places")));
+        assert_that!(accounts.is_empty(), is(true));
+    }
+
+    #[test]
+    fn test_invalid_row() {
+        let mut accounts = HashMap::new();
+        let data = "\
+client,available,held,total,locked
+1,1.5,0.5,2.0,maybe
+";
+
+        let result = load_opening_balances(data.as_bytes(), &mut accounts);
+
+        assert_that!(matches!(result, Err(OpeningBalancesError::Csv(_))), is(true));
+    }
+}
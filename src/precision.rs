@@ -0,0 +1,140 @@
+use std::str::FromStr;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Serializer;
+
+use crate::error::ErrorKind;
+
+// Balances are always written with this number of decimal places
+pub const BALANCE_PLACES: u32 = 4;
+
+// What to do with amounts that have more decimal places than allowed
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum RoundingPolicy {
+    #[default]
+    Reject,
+    // Rounds to the nearest, and to the even one when halfway (banker's rounding)
+    HalfEven,
+    Truncate,
+}
+
+impl FromStr for RoundingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(RoundingPolicy::Reject),
+            "half-even" => Ok(RoundingPolicy::HalfEven),
+            "truncate" => Ok(RoundingPolicy::Truncate),
+            _ => Err(format!("unknown rounding policy {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Precision {
+    pub places: u32,
+    pub policy: RoundingPolicy,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self { places: BALANCE_PLACES, policy: RoundingPolicy::default() }
+    }
+}
+
+impl Precision {
+    // Amounts can't be more precise than balances, since these are written with a fixed scale
+    pub fn with_places(self, places: u32) -> Result<Self, String> {
+        if places > BALANCE_PLACES {
+            return Err(format!("precision cannot exceed {} decimal places", BALANCE_PLACES));
+        }
+
+        Ok(Self { places, ..self })
+    }
+
+    pub fn apply(&self, amount: Decimal) -> Result<Decimal, ErrorKind> {
+        // Trailing zeros don't count, e.g. 2.50000 has two decimal places
+        let amount = amount.normalize();
+
+        if amount.scale() <= self.places {
+            return Ok(amount);
+        }
+
+        match self.policy {
+            RoundingPolicy::Reject => Err(ErrorKind::ExcessPrecision),
+            RoundingPolicy::HalfEven => Ok(amount.round_dp_with_strategy(self.places, RoundingStrategy::MidpointNearestEven)),
+            RoundingPolicy::Truncate => Ok(amount.round_dp_with_strategy(self.places, RoundingStrategy::ToZero)),
+        }
+    }
+}
+
+// Writes balances with a fixed scale, e.g. 2 as 2.0000
+pub fn serialize_balance<S: Serializer>(balance: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&rounded_balance(*balance))
+}
+
+// The balance as it is written
+pub fn rounded_balance(mut balance: Decimal) -> Decimal {
+    balance.rescale(BALANCE_PLACES);
+    balance
+}
+
+// Whether a loaded balance can be written back as it is. Balances written by the engine always can.
+pub fn fits_balance_places(balance: Decimal) -> bool {
+    balance.normalize().scale() <= BALANCE_PLACES
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn precision(policy: RoundingPolicy) -> Precision {
+        Precision { places: 4, policy }
+    }
+
+    #[test]
+    fn test_within_precision() {
+        assert_that!(precision(RoundingPolicy::Reject).apply(dec!(3.1234)), is(equal_to(Ok(dec!(3.1234)))));
+        assert_that!(precision(RoundingPolicy::Reject).apply(dec!(2.500000)), is(equal_to(Ok(dec!(2.5)))));
+    }
+
+    #[test]
+    fn test_reject() {
+        assert_that!(precision(RoundingPolicy::Reject).apply(dec!(3.12345)), is(equal_to(Err(ErrorKind::ExcessPrecision))));
+    }
+
+    #[test]
+    fn test_round_half_even() {
+        assert_that!(precision(RoundingPolicy::HalfEven).apply(dec!(3.12345)), is(equal_to(Ok(dec!(3.1234)))));
+        assert_that!(precision(RoundingPolicy::HalfEven).apply(dec!(3.12355)), is(equal_to(Ok(dec!(3.1236)))));
+        assert_that!(precision(RoundingPolicy::HalfEven).apply(dec!(3.12346)), is(equal_to(Ok(dec!(3.1235)))));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_that!(precision(RoundingPolicy::Truncate).apply(dec!(3.12349)), is(equal_to(Ok(dec!(3.1234)))));
+        assert_that!(precision(RoundingPolicy::Truncate).apply(dec!(-3.12349)), is(equal_to(Ok(dec!(-3.1234)))));
+    }
+
+    #[test]
+    fn test_places() {
+        assert_that!(Precision::default().with_places(2), is(equal_to(Ok(Precision { places: 2, policy: RoundingPolicy::Reject }))));
+        assert_that!(Precision::default().with_places(5), is(equal_to(Err("precision cannot exceed 4 decimal places".to_string()))));
+    }
+
+    #[test]
+    fn test_balance_places() {
+        assert_that!(fits_balance_places(dec!(0.1234000)), is(true));
+        assert_that!(fits_balance_places(dec!(0.00015)), is(false));
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_that!("half-even".parse::<RoundingPolicy>(), is(equal_to(Ok(RoundingPolicy::HalfEven))));
+        assert_that!("up".parse::<RoundingPolicy>(), is(equal_to(Err("unknown rounding policy up".to_string()))));
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+
+use crate::store::StoreError;
+
+// Keeps track of the ids already used by deposits and withdrawals, to detect replayed transactions.
+// Like the other stores, every operation can fail, so that on-disk or remote backends can be plugged in.
+pub trait SeenTransactions {
+    // Returns false if the transaction id had already been seen
+    fn insert(&mut self, tx: u32) -> Result<bool, StoreError>;
+
+    fn contains(&self, tx: u32) -> Result<bool, StoreError>;
+
+    fn ids(&self) -> Box<dyn Iterator<Item=Result<u32, StoreError>> + '_>;
+}
+
+impl SeenTransactions for HashSet<u32> {
+    fn insert(&mut self, tx: u32) -> Result<bool, StoreError> {
+        Ok(HashSet::insert(self, tx))
+    }
+
+    fn contains(&self, tx: u32) -> Result<bool, StoreError> {
+        Ok(HashSet::contains(self, &tx))
+    }
+
+    fn ids(&self) -> Box<dyn Iterator<Item=Result<u32, StoreError>> + '_> {
+        Box::new(self.iter().copied().map(Ok))
+    }
+}
+
+const PAGE_BITS: u32 = 16;
+const WORDS_PER_PAGE: usize = (1 << PAGE_BITS) / 64;
+
+// Uses one bit per transaction id, allocated in pages when first needed, so that
+// even the whole u32 range fits in 512MB
+pub struct TransactionBitmap {
+    pages: Vec<Option<Box<[u64; WORDS_PER_PAGE]>>>,
+}
+
+impl Default for TransactionBitmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransactionBitmap {
+    pub fn new() -> Self {
+        Self {
+            pages: vec![],
+        }
+    }
+
+    fn position(tx: u32) -> (usize, usize, u64) {
+        let page = (tx >> PAGE_BITS) as usize;
+        let offset = (tx & ((1 << PAGE_BITS) - 1)) as usize;
+
+        (page, offset / 64, 1 << (offset % 64))
+    }
+
+    // Returns false if the bit was already set
+    pub fn set(&mut self, tx: u32) -> bool {
+        let (page, word, mask) = Self::position(tx);
+
+        if self.pages.len() <= page {
+            self.pages.resize_with(page + 1, || None);
+        }
+
+        let words = self.pages[page].get_or_insert_with(|| Box::new([0; WORDS_PER_PAGE]));
+        let seen = words[word] & mask != 0;
+        words[word] |= mask;
+
+        !seen
+    }
+
+    pub fn is_set(&self, tx: u32) -> bool {
+        let (page, word, mask) = Self::position(tx);
+
+        match self.pages.get(page) {
+            Some(Some(words)) => words[word] & mask != 0,
+            _ => false,
+        }
+    }
+
+    fn set_ids(&self) -> impl Iterator<Item=u32> + '_ {
+        self.pages.iter().enumerate()
+            .filter_map(|(page, words)| words.as_ref().map(|words| (page, words)))
+            .flat_map(|(page, words)| words.iter().enumerate().flat_map(move |(word, bits)| {
+                (0..64).filter(move |bit| bits & (1 << bit) != 0)
+                    .map(move |bit| ((page as u32) << PAGE_BITS) + (word * 64 + bit) as u32)
+            }))
+    }
+}
+
+impl SeenTransactions for TransactionBitmap {
+    fn insert(&mut self, tx: u32) -> Result<bool, StoreError> {
+        Ok(self.set(tx))
+    }
+
+    fn contains(&self, tx: u32) -> Result<bool, StoreError> {
+        Ok(self.is_set(tx))
+    }
+
+    fn ids(&self) -> Box<dyn Iterator<Item=Result<u32, StoreError>> + '_> {
+        Box::new(self.set_ids().map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+
+    use super::*;
+
+    #[test]
+    fn test_bitmap_insert() {
+        let mut seen = TransactionBitmap::new();
+
+        assert_that!(seen.insert(1), is(equal_to(Ok(true))));
+        assert_that!(seen.insert(1), is(equal_to(Ok(false))));
+        assert_that!(seen.contains(1), is(equal_to(Ok(true))));
+        assert_that!(seen.contains(2), is(equal_to(Ok(false))));
+    }
+
+    #[test]
+    fn test_bitmap_boundaries() {
+        let mut seen = TransactionBitmap::new();
+
+        assert_that!(seen.set(0), is(true));
+        assert_that!(seen.set(u32::MAX), is(true));
+        assert_that!(seen.set(u32::MAX), is(false));
+        assert_that!(seen.is_set(0), is(true));
+        assert_that!(seen.is_set(u32::MAX - 1), is(false));
+    }
+
+    #[test]
+    fn test_bitmap_ids() {
+        let mut seen = TransactionBitmap::new();
+        seen.set(u32::MAX);
+        seen.set(70000);
+        seen.set(3);
+
+        assert_that!(seen.ids().collect::<Result<Vec<_>, _>>(), is(equal_to(Ok(vec![3, 70000, u32::MAX]))));
+    }
+
+    #[test]
+    fn test_hash_set_insert() {
+        let mut seen = HashSet::new();
+
+        assert_that!(SeenTransactions::insert(&mut seen, 1), is(equal_to(Ok(true))));
+        assert_that!(SeenTransactions::insert(&mut seen, 1), is(equal_to(Ok(false))));
+        assert_that!(SeenTransactions::contains(&seen, 1), is(equal_to(Ok(true))));
+    }
+}
@@ -0,0 +1,424 @@
+use std::{fmt, io};
+
+use std::collections::HashMap;
+
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use rust_decimal::Decimal;
+
+use crate::Account;
+use crate::invariants;
+use crate::seen_transactions::SeenTransactions;
+use crate::ledger::LedgerError;
+use crate::precision::fits_balance_places;
+use crate::store::{AccountStore, StoreError, TransactionStore};
+use crate::transaction::{DisputableTransaction, TransactionType};
+use crate::transaction_state::TransactionState;
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// A snapshot is a headerless CSV file, where the first field of every record tells what it contains:
+//
+//   version,1
+//   account,<client>,<available>,<held>,<locked>
+//   unlock,<client>,<reason>
+//   held,<client>,<amount>
+//   transaction,<tx>,<type>,<client>,<amount>,<state>
+//   seen,<tx>
+//
+// Unlock reasons always come after the account they belong to. Held funds that don't belong to a disputed
+// transaction, like opening ones, are listed on their own, so that the held funds of every account can be checked
+// against its disputes. All the transactions are included, even from a persistent store, which may have moved on
+// since the snapshot was saved: that's why snapshots are only restored into empty stores.
+// Balances and amounts can't have more decimal places than the output, so that they can always be written back.
+#[derive(Debug)]
+pub enum SnapshotError {
+    Csv(csv::Error),
+    Io(io::Error),
+    Store(StoreError),
+    Ledger(LedgerError),
+    UnsupportedVersion(String),
+    // The position of the record that could not be understood
+    InvalidRecord(u64),
+    // The held funds of the client differ from its disputes
+    HeldMismatch(u16),
+    // The stores to restore into already have some content
+    NotEmpty,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Csv(err) => write!(f, "Could not read snapshot: {}", err),
+            SnapshotError::Io(err) => write!(f, "Could not write snapshot: {}", err),
+            SnapshotError::Store(err) => write!(f, "Store failure: {}", err),
+            SnapshotError::Ledger(err) => write!(f, "{}", err),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "Unsupported snapshot version \"{}\"", version),
+            SnapshotError::InvalidRecord(line) => write!(f, "Invalid snapshot record at line {}", line),
+            SnapshotError::HeldMismatch(client) => write!(f, "Held funds of client \"{}\" do not match its disputes", client),
+            SnapshotError::NotEmpty => write!(f, "Snapshots can only be restored into empty stores"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<csv::Error> for SnapshotError {
+    fn from(err: csv::Error) -> Self {
+        SnapshotError::Csv(err)
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<StoreError> for SnapshotError {
+    fn from(err: StoreError) -> Self {
+        SnapshotError::Store(err)
+    }
+}
+
+impl From<LedgerError> for SnapshotError {
+    fn from(err: LedgerError) -> Self {
+        SnapshotError::Ledger(err)
+    }
+}
+
+// The open disputes of every client are the amounts held by the engine, including the ones without a transaction
+pub fn write_snapshot<W, A, T, S>(writer: W, accounts: &A, transactions: &T, seen_transactions: &S, open_disputes: &HashMap<u16, Decimal>) -> Result<(), SnapshotError>
+    where W: io::Write, A: AccountStore, T: TransactionStore, S: SeenTransactions {
+    let mut writer = WriterBuilder::new().has_headers(false).flexible(true).from_writer(writer);
+    let disputed = invariants::open_disputes(transactions)?;
+
+    writer.serialize(("version", SNAPSHOT_VERSION))?;
+
+    for account in accounts.all()? {
+        writer.serialize(("account", account.client, account.available, account.held, account.locked))?;
+
+        for reason in &account.unlock_reasons {
+            writer.serialize(("unlock", account.client, reason))?;
+        }
+
+        let held = open_disputes.get(&account.client).copied().unwrap_or_default();
+        let without_transaction = held.checked_sub(disputed.get(&account.client).copied().unwrap_or_default()).ok_or(StoreError::Corrupted)?;
+        if !without_transaction.is_zero() {
+            writer.serialize(("held", account.client, without_transaction))?;
+        }
+    }
+
+    for (tx, transaction) in transactions.all()? {
+        writer.serialize(("transaction", tx, transaction.transaction_type, transaction.client, transaction.amount, transaction.state))?;
+    }
+
+    for tx in seen_transactions.ids() {
+        writer.serialize(("seen", tx?))?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Adds the content of the snapshot to the given stores, which must be empty, and returns the open disputes of every
+// client. The held funds of every account must match them.
+pub fn read_snapshot<R, A, T, S>(reader: R, accounts: &mut A, transactions: &mut T, seen_transactions: &mut S) -> Result<HashMap<u16, Decimal>, SnapshotError>
+    where R: io::Read, A: AccountStore, T: TransactionStore, S: SeenTransactions {
+    let mut reader = ReaderBuilder::new().has_headers(false).flexible(true).from_reader(reader);
+    let mut records = reader.records();
+    let mut held_without_transaction = HashMap::new();
+
+    if !accounts.all()?.is_empty() || !transactions.all()?.is_empty() || seen_transactions.ids().next().is_some() {
+        return Err(SnapshotError::NotEmpty);
+    }
+
+    match records.next() {
+        Some(record) => check_version(&record?)?,
+        None => return Err(SnapshotError::UnsupportedVersion(String::new())),
+    }
+
+    for record in records {
+        let record = record?;
+        let invalid = || SnapshotError::InvalidRecord(record.position().map_or(0, |position| position.line()));
+
+        match record.get(0) {
+            Some("account") => {
+                let (_, client, available, held, locked): (String, u16, Decimal, Decimal, bool) =
+                    record.deserialize(None).map_err(|_| invalid())?;
+                if available < Decimal::ZERO || held < Decimal::ZERO || !fits_balance_places(available) || !fits_balance_places(held) {
+                    return Err(invalid());
+                }
+                available.checked_add(held).ok_or_else(invalid)?;
+                accounts.update(Account::new(client, available, held, locked))?;
+            }
+            Some("unlock") => {
+                let (_, client, reason): (String, u16, String) = record.deserialize(None).map_err(|_| invalid())?;
+                let mut account = accounts.get(client)?.ok_or_else(invalid)?;
+                account.unlock_reasons.push(reason);
+                accounts.update(account)?;
+            }
+            Some("held") => {
+                let (_, client, amount): (String, u16, Decimal) = record.deserialize(None).map_err(|_| invalid())?;
+                if !fits_balance_places(amount) {
+                    return Err(invalid());
+                }
+                held_without_transaction.insert(client, amount);
+            }
+            Some("transaction") => {
+                let (_, tx, transaction_type, client, amount, state): (String, u32, TransactionType, u16, Decimal, TransactionState) =
+                    record.deserialize(None).map_err(|_| invalid())?;
+                if !fits_balance_places(amount) {
+                    return Err(invalid());
+                }
+                transactions.record(tx, DisputableTransaction { transaction_type, client, amount, state })?;
+            }
+            Some("seen") => {
+                let (_, tx): (String, u32) = record.deserialize(None).map_err(|_| invalid())?;
+                seen_transactions.insert(tx)?;
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    let mut open_disputes = invariants::open_disputes(transactions)?;
+    for (client, amount) in held_without_transaction {
+        let sum: &mut Decimal = open_disputes.entry(client).or_default();
+        *sum = sum.checked_add(amount).ok_or(SnapshotError::HeldMismatch(client))?;
+    }
+
+    for account in accounts.all()? {
+        if account.held != open_disputes.get(&account.client).copied().unwrap_or_default() {
+            return Err(SnapshotError::HeldMismatch(account.client));
+        }
+    }
+
+    Ok(open_disputes)
+}
+
+fn check_version(record: &StringRecord) -> Result<(), SnapshotError> {
+    let version = record.get(1).unwrap_or_default();
+
+    match (record.get(0), version.parse::<u32>()) {
+        (Some("version"), Ok(SNAPSHOT_VERSION)) => Ok(()),
+        _ => Err(SnapshotError::UnsupportedVersion(version.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use crate::{Account, Transaction};
+    use crate::account::LockPolicy;
+    use crate::engine::Engine;
+    use crate::engine_extended::EngineExtended;
+    use crate::engine_simple::EngineSimple;
+    use crate::error::ErrorKind;
+    use crate::file_transaction_store::FileTransactionStore;
+    use crate::file_transaction_store::tests::temp_path;
+    use crate::transaction::TransactionType::*;
+
+    use super::*;
+
+    fn snapshot_of(engine: &EngineSimple) -> Vec<u8> {
+        let mut snapshot = vec![];
+        engine.save_snapshot(&mut snapshot).unwrap();
+
+        snapshot
+    }
+
+    #[test]
+    fn test_restore_accounts() {
+        let mut engine = EngineSimple::default();
+        engine.analyze(vec![
+            Transaction::new(Deposit, 1, 1, Some(dec!(10.5))),
+            Transaction::new(Deposit, 1, 2, Some(dec!(5))),
+            Transaction::new(Dispute, 1, 2, None),
+            Transaction::new(Deposit, 2, 3, Some(dec!(3))),
+            Transaction::new(Dispute, 2, 3, None),
+            Transaction::new(Chargeback, 2, 3, None),
+        ]).unwrap();
+        engine.unlock(2, "Checked with the bank").unwrap();
+
+        let mut restored = EngineSimple::default();
+        restored.restore_snapshot(snapshot_of(&engine).as_slice()).unwrap();
+
+        assert_that!(restored.accounts().unwrap(), is(equal_to(engine.accounts().unwrap())));
+    }
+
+    #[test]
+    fn test_restore_transactions() {
+        let mut engine = EngineSimple::default();
+        engine.analyze(vec![
+            Transaction::new(Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Deposit, 1, 2, Some(dec!(5))),
+            Transaction::new(Dispute, 1, 2, None),
+        ]).unwrap();
+
+        let mut restored = EngineSimple::default();
+        restored.restore_snapshot(snapshot_of(&engine).as_slice()).unwrap();
+        let (accounts, errors) = restored.analyze(vec![
+            Transaction::new(Resolve, 1, 2, None),
+            Transaction::new(Dispute, 1, 1, None),
+            Transaction::new(Deposit, 1, 2, Some(dec!(1))),
+        ]).unwrap();
+
+        assert_that!(accounts, is(equal_to(vec![Account::new(1, dec!(5), dec!(10), false)])));
+        assert_that!(errors.len(), is(equal_to(1)));
+        assert_that!(errors[0].kind, is(equal_to(ErrorKind::DuplicateTransaction)));
+    }
+
+    #[test]
+    fn test_lock_policy_of_restoring_engine() {
+        let mut engine = EngineSimple::default();
+        engine.analyze(vec![
+            Transaction::new(Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Deposit, 1, 2, Some(dec!(5))),
+            Transaction::new(Dispute, 1, 2, None),
+            Transaction::new(Chargeback, 1, 2, None),
+        ]).unwrap();
+
+        let mut restored = EngineSimple::default().with_lock_policy(LockPolicy::RejectAll);
+        restored.restore_snapshot(snapshot_of(&engine).as_slice()).unwrap();
+        let (_, errors) = restored.analyze(vec![Transaction::new(Dispute, 1, 1, None)]).unwrap();
+
+        assert_that!(errors.len(), is(equal_to(1)));
+        assert_that!(errors[0].kind, is(equal_to(ErrorKind::AccountLocked)));
+    }
+
+    #[test]
+    fn test_snapshot_format() {
+        let mut accounts = HashMap::new();
+        let mut transactions = HashMap::new();
+        let mut seen_transactions = HashSet::new();
+        let deposit = Transaction::new(Deposit, 1, 4, Some(dec!(2.5)));
+        accounts.update(Account::new(1, dec!(2.5), dec!(0), false)).unwrap();
+        transactions.record(4, DisputableTransaction::new(&deposit, dec!(2.5))).unwrap();
+        SeenTransactions::insert(&mut seen_transactions, 4).unwrap();
+
+        let mut snapshot = vec![];
+        write_snapshot(&mut snapshot, &accounts, &transactions, &seen_transactions, &HashMap::new()).unwrap();
+
+        assert_that!(String::from_utf8(snapshot).unwrap().as_str(), is(equal_to("\
+version,1
+account,1,2.5,0,false
+transaction,4,deposit,1,2.5,applied
+seen,4
+")));
+    }
+
+    #[test]
+    fn test_restore_opening_held_funds() {
+        let mut engine = EngineSimple::default();
+        engine.load_accounts("client,available,held,total,locked\n1,10,5,15,false\n".as_bytes()).unwrap();
+        engine.analyze(vec![
+            Transaction::new(Deposit, 1, 1, Some(dec!(2))),
+            Transaction::new(Dispute, 1, 1, None),
+        ]).unwrap();
+        let snapshot = snapshot_of(&engine);
+
+        let mut restored = EngineSimple::default();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        let (accounts, errors) = restored.analyze(vec![Transaction::new(Resolve, 1, 1, None)]).unwrap();
+
+        assert_that!(String::from_utf8(snapshot).unwrap().contains("held,1,5\n"), is(true));
+        assert_that!(accounts, is(equal_to(vec![Account::new(1, dec!(12), dec!(5), false)])));
+        assert_that!(errors.len(), is(equal_to(0)));
+    }
+
+    #[test]
+    fn test_restore_persistent_transactions() {
+        let path = temp_path("snapshot-transactions");
+        let restored_path = temp_path("snapshot-transactions");
+        let mut engine = EngineExtended::new(HashMap::new(), FileTransactionStore::open(&path).unwrap(), HashSet::new());
+        engine.analyze(vec![
+            Transaction::new(Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(Dispute, 1, 1, None),
+        ]).unwrap();
+        let mut snapshot = vec![];
+        engine.save_snapshot(&mut snapshot).unwrap();
+
+        let mut restored = EngineExtended::new(HashMap::new(), FileTransactionStore::open(&restored_path).unwrap(), HashSet::new());
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        let (accounts, _) = restored.analyze(vec![Transaction::new(Chargeback, 1, 1, None)]).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&restored_path);
+
+        assert_that!(accounts, is(equal_to(vec![Account::new(1, dec!(0), dec!(0), true)])));
+    }
+
+    #[test]
+    fn test_restore_into_store_that_moved_on() {
+        let path = temp_path("snapshot-transactions");
+        let mut engine = EngineExtended::new(HashMap::new(), FileTransactionStore::open(&path).unwrap(), HashSet::new());
+        engine.analyze(vec![Transaction::new(Deposit, 1, 1, Some(dec!(10)))]).unwrap();
+        let mut snapshot = vec![];
+        engine.save_snapshot(&mut snapshot).unwrap();
+        engine.analyze(vec![Transaction::new(Deposit, 1, 2, Some(dec!(5)))]).unwrap();
+
+        let mut restored = EngineExtended::new(HashMap::new(), FileTransactionStore::open(&path).unwrap(), HashSet::new());
+        let result = restored.restore_snapshot(snapshot.as_slice());
+        let _ = std::fs::remove_file(&path);
+
+        assert_that!(result.unwrap_err().to_string().as_str(), is(equal_to("Snapshots can only be restored into empty stores")));
+    }
+
+    #[test]
+    fn test_held_funds_without_disputes() {
+        let mut engine = EngineSimple::default();
+
+        let result = engine.restore_snapshot("version,1\naccount,1,10,5,false\n".as_bytes());
+
+        assert_that!(result.unwrap_err().to_string().as_str(), is(equal_to("Held funds of client \"1\" do not match its disputes")));
+    }
+
+    #[test]
+    fn test_negative_balance() {
+        let mut engine = EngineSimple::default();
+
+        let result = engine.restore_snapshot("version,1\naccount,1,10,-5,false\n".as_bytes());
+
+        assert_that!(result.unwrap_err().to_string().as_str(), is(equal_to("Invalid snapshot record at line 2")));
+    }
+
+    #[test]
+    fn test_excess_precision() {
+        let mut engine = EngineSimple::default();
+
+        let result = engine.restore_snapshot("version,1\naccount,1,0.00015,0,false\n".as_bytes());
+
+        assert_that!(result.unwrap_err().to_string().as_str(), is(equal_to("Invalid snapshot record at line 2")));
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut engine = EngineSimple::default();
+
+        let result = engine.restore_snapshot("version,2\n".as_bytes());
+
+        assert_that!(result.unwrap_err().to_string().as_str(), is(equal_to("Unsupported snapshot version \"2\"")));
+    }
+
+    #[test]
+    fn test_overflowing_account() {
+        let mut engine = EngineSimple::default();
+        let snapshot = format!("version,1\naccount,1,{},{},false\n", Decimal::MAX, Decimal::MAX);
+
+        let result = engine.restore_snapshot(snapshot.as_bytes());
+
+        assert_that!(result.unwrap_err().to_string().as_str(), is(equal_to("Invalid snapshot record at line 2")));
+    }
+
+    #[test]
+    fn test_invalid_record() {
+        let mut engine = EngineSimple::default();
+
+        let result = engine.restore_snapshot("version,1\naccount,1,2.5,0,false\ntransaction,4,deposit\n".as_bytes());
+
+        assert_that!(result.unwrap_err().to_string().as_str(), is(equal_to("Invalid snapshot record at line 3")));
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::{fmt, io};
+
+use crate::Account;
+use crate::transaction::DisputableTransaction;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StoreError {
+    // The backend could not be read or written
+    Io(io::ErrorKind),
+    // The backend returned data that cannot be decoded
+    Corrupted,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(kind) => write!(f, "{}", kind),
+            StoreError::Corrupted => write!(f, "corrupted data"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err.kind())
+    }
+}
+
+// Where the engine keeps the accounts. Every operation can fail, so that remote or on-disk
+// backends can be plugged in, and the batched ones can be overridden to save round trips.
+pub trait AccountStore {
+    fn get(&self, client: u16) -> Result<Option<Account>, StoreError>;
+
+    // Only called for clients that are not in the store yet
+    fn create(&mut self, account: Account) -> Result<(), StoreError>;
+
+    fn update(&mut self, account: Account) -> Result<(), StoreError>;
+
+    fn all(&self) -> Result<Vec<Account>, StoreError>;
+
+    fn get_many(&self, clients: &[u16]) -> Result<Vec<Option<Account>>, StoreError> {
+        clients.iter().map(|client| self.get(*client)).collect()
+    }
+
+    fn update_many(&mut self, accounts: Vec<Account>) -> Result<(), StoreError> {
+        accounts.into_iter().try_for_each(|account| self.update(account))
+    }
+}
+
+// Where the engine keeps the applied transactions, so that they can be disputed later
+pub trait TransactionStore {
+    // Adds a new transaction, or replaces it with its updated state
+    fn record(&mut self, tx: u32, transaction: DisputableTransaction) -> Result<(), StoreError>;
+
+    fn lookup(&self, tx: u32) -> Result<Option<DisputableTransaction>, StoreError>;
+
+    fn remove(&mut self, tx: u32) -> Result<(), StoreError>;
+
+    fn all(&self) -> Result<Vec<(u32, DisputableTransaction)>, StoreError>;
+
+    fn record_many(&mut self, transactions: Vec<(u32, DisputableTransaction)>) -> Result<(), StoreError> {
+        transactions.into_iter().try_for_each(|(tx, transaction)| self.record(tx, transaction))
+    }
+
+    fn lookup_many(&self, txs: &[u32]) -> Result<Vec<Option<DisputableTransaction>>, StoreError> {
+        txs.iter().map(|tx| self.lookup(*tx)).collect()
+    }
+}
+
+impl AccountStore for HashMap<u16, Account> {
+    fn get(&self, client: u16) -> Result<Option<Account>, StoreError> {
+        Ok(HashMap::get(self, &client).cloned())
+    }
+
+    fn create(&mut self, account: Account) -> Result<(), StoreError> {
+        self.insert(account.client, account);
+        Ok(())
+    }
+
+    fn update(&mut self, account: Account) -> Result<(), StoreError> {
+        self.insert(account.client, account);
+        Ok(())
+    }
+
+    fn all(&self) -> Result<Vec<Account>, StoreError> {
+        Ok(self.values().cloned().collect())
+    }
+}
+
+impl TransactionStore for HashMap<u32, DisputableTransaction> {
+    fn record(&mut self, tx: u32, transaction: DisputableTransaction) -> Result<(), StoreError> {
+        self.insert(tx, transaction);
+        Ok(())
+    }
+
+    fn lookup(&self, tx: u32) -> Result<Option<DisputableTransaction>, StoreError> {
+        Ok(self.get(&tx).copied())
+    }
+
+    fn remove(&mut self, tx: u32) -> Result<(), StoreError> {
+        HashMap::remove(self, &tx);
+        Ok(())
+    }
+
+    fn all(&self) -> Result<Vec<(u32, DisputableTransaction)>, StoreError> {
+        Ok(self.iter().map(|(tx, transaction)| (*tx, *transaction)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use crate::Transaction;
+    use crate::transaction::TransactionType::Deposit;
+
+    use super::*;
+
+    #[test]
+    fn test_batched_accounts() {
+        let mut store = HashMap::new();
+        store.update_many(vec![Account::empty(1), Account::empty(2)]).unwrap();
+
+        assert_that!(store.get_many(&[1, 3]).unwrap(), is(equal_to(vec![Some(Account::empty(1)), None])));
+    }
+
+    #[test]
+    fn test_batched_transactions() {
+        let transaction = DisputableTransaction::new(&Transaction::new(Deposit, 1, 1, None), dec!(10));
+        let mut store = HashMap::new();
+        store.record_many(vec![(1, transaction), (2, transaction)]).unwrap();
+        TransactionStore::remove(&mut store, 2).unwrap();
+
+        assert_that!(store.lookup_many(&[1, 2]).unwrap(), is(equal_to(vec![Some(transaction), None])));
+    }
+}
@@ -1,18 +1,151 @@
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize)]
+use crate::error::{EngineError, ErrorKind};
+use crate::precision::Precision;
+use crate::transaction_state::TransactionState;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
 pub struct Transaction {
     #[serde(rename(deserialize = "type"))]
-    pub transaction_type: String,
+    pub transaction_type: TransactionType,
     pub client: u16,
     pub tx: u32,
+    // Disputes, resolutions and chargebacks refer to an existing transaction, so they don't carry an amount
+    pub amount: Option<Decimal>,
+}
+
+// What needs to be remembered about an applied transaction, in order to dispute it later
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DisputableTransaction {
+    // Either a deposit or a withdrawal, which decides in which direction a dispute moves the funds
+    pub transaction_type: TransactionType,
+    pub client: u16,
     pub amount: Decimal,
+    pub state: TransactionState,
+}
+
+impl DisputableTransaction {
+    pub fn new(transaction: &Transaction, amount: Decimal) -> Self {
+        Self { transaction_type: transaction.transaction_type, client: transaction.client, amount, state: TransactionState::Applied }
+    }
+}
+
+impl Transaction {
+    // Meant to be applied right after parsing
+    pub fn with_precision(mut self, precision: Precision) -> Result<Self, EngineError> {
+        if let Some(amount) = self.amount {
+            self.amount = Some(precision.apply(amount).map_err(|err| EngineError::new(err, &self, Some(amount)))?);
+        }
+
+        Ok(self)
+    }
+
+    pub fn required_amount(&self) -> Result<Decimal, EngineError> {
+        self.amount.ok_or_else(|| EngineError::new(ErrorKind::MissingAmount, self, None))
+    }
+
+    // Deposits and withdrawals with an amount use up their id, even when they are rejected
+    pub fn uses_id(&self) -> bool {
+        matches!(self.transaction_type, TransactionType::Deposit | TransactionType::Withdrawal) && self.amount.is_some()
+    }
 }
 
 #[cfg(test)]
 impl Transaction {
-    pub fn new(transaction_type: String, client: u16, tx: u32, amount: Decimal) -> Self {
-        Self { transaction_type, client, tx, amount}
+    pub fn new(transaction_type: TransactionType, client: u16, tx: u32, amount: Option<Decimal>) -> Self {
+        Self { transaction_type, client, tx, amount }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use csv::{ReaderBuilder, Trim};
+    use hamcrest::*;
+    use proptest::prelude::*;
+    use rust_decimal_macros::dec;
+
+    use crate::precision::RoundingPolicy;
+
+    use super::*;
+
+    // Random transactions on a few clients and ids, so that disputes often refer to existing transactions
+    pub fn transaction(clients: u16, ids: u32) -> impl Strategy<Value=Transaction> {
+        let transaction_type = prop_oneof![
+            3 => Just(TransactionType::Deposit),
+            2 => Just(TransactionType::Withdrawal),
+            2 => Just(TransactionType::Dispute),
+            1 => Just(TransactionType::Resolve),
+            1 => Just(TransactionType::Chargeback),
+        ];
+        let amount = proptest::option::weighted(0.95, 1..1_000_000i64).prop_map(|amount| amount.map(|amount| Decimal::new(amount, 4)));
+
+        (transaction_type, 1..=clients, 1..ids, amount)
+            .prop_map(|(transaction_type, client, tx, amount)| Transaction::new(transaction_type, client, tx, amount))
+    }
+
+    fn parse(data: &str) -> Vec<Result<Transaction, csv::Error>> {
+        ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes())
+            .deserialize()
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_deposit() {
+        let transactions = parse("type, client, tx, amount\ndeposit, 1, 2, 3.5\n");
+        let transaction = transactions[0].as_ref().unwrap();
+
+        assert_that!(transaction.transaction_type, is(equal_to(TransactionType::Deposit)));
+        assert_that!(transaction.client, is(equal_to(1)));
+        assert_that!(transaction.tx, is(equal_to(2)));
+        assert_that!(transaction.amount, is(equal_to(Some(dec!(3.5)))));
+    }
+
+    #[test]
+    fn test_parse_without_amount() {
+        let transactions = parse("type, client, tx, amount\ndispute, 1, 1,\nresolve, 1, 1\n");
+
+        assert_that!(transactions[0].as_ref().unwrap().amount, is(equal_to(None)));
+        assert_that!(transactions[1].as_ref().unwrap().amount, is(equal_to(None)));
+    }
+
+    #[test]
+    fn test_reject_unknown_type() {
+        let transactions = parse("type, client, tx, amount\ndeposit, 1, 1, 1.0\nunknown, 1, 2, 1.0\n");
+
+        assert!(transactions[0].is_ok());
+        let err = transactions[1].as_ref().unwrap_err();
+        assert_that!(err.position().unwrap().line(), is(equal_to(3)));
+    }
+
+    #[test]
+    fn test_required_amount() {
+        let deposit = Transaction::new(TransactionType::Deposit, 1, 2, None);
+
+        assert_that!(deposit.required_amount().unwrap_err().kind, is(equal_to(ErrorKind::MissingAmount)));
+    }
+
+    #[test]
+    fn test_with_precision() {
+        let deposit = Transaction::new(TransactionType::Deposit, 1, 2, Some(dec!(1.00005)));
+        let dispute = Transaction::new(TransactionType::Dispute, 1, 2, None);
+        let rounding = Precision { places: 4, policy: RoundingPolicy::HalfEven };
+
+        assert_that!(deposit.with_precision(rounding).unwrap().amount, is(equal_to(Some(dec!(1.0000)))));
+        assert_that!(deposit.with_precision(Precision::default()).unwrap_err().kind, is(equal_to(ErrorKind::ExcessPrecision)));
+        assert_that!(dispute.with_precision(Precision::default()).unwrap().amount, is(equal_to(None)));
     }
 }
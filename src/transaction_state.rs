@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::ErrorKind;
+use crate::transaction::TransactionType;
+
+// Lifecycle of an applied deposit or withdrawal:
+//
+//   Applied -> Disputed -> Resolved -> Disputed -> ...
+//                       -> ChargedBack
+//
+// A resolved transaction can be disputed again, while a charged back one is final.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionState {
+    Applied,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl TransactionState {
+    pub fn transition(self, transaction_type: TransactionType) -> Result<TransactionState, ErrorKind> {
+        use TransactionState::*;
+
+        match (self, transaction_type) {
+            (Applied | Resolved, TransactionType::Dispute) => Ok(Disputed),
+            (Disputed, TransactionType::Dispute) => Err(ErrorKind::AlreadyDisputed),
+            (Disputed, TransactionType::Resolve) => Ok(Resolved),
+            (Disputed, TransactionType::Chargeback) => Ok(ChargedBack),
+            (ChargedBack, _) => Err(ErrorKind::AlreadyChargedBack),
+            (_, TransactionType::Resolve | TransactionType::Chargeback) => Err(ErrorKind::NotDisputed),
+            (_, TransactionType::Deposit | TransactionType::Withdrawal) => Err(ErrorKind::DuplicateTransaction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+
+    use crate::transaction::TransactionType::*;
+
+    use super::*;
+    use super::TransactionState::*;
+
+    #[test]
+    fn test_dispute() {
+        assert_that!(Applied.transition(Dispute), is(equal_to(Ok(Disputed))));
+        assert_that!(Disputed.transition(Dispute), is(equal_to(Err(ErrorKind::AlreadyDisputed))));
+    }
+
+    #[test]
+    fn test_settle_dispute() {
+        assert_that!(Disputed.transition(Resolve), is(equal_to(Ok(Resolved))));
+        assert_that!(Disputed.transition(Chargeback), is(equal_to(Ok(ChargedBack))));
+    }
+
+    #[test]
+    fn test_settle_undisputed() {
+        assert_that!(Applied.transition(Resolve), is(equal_to(Err(ErrorKind::NotDisputed))));
+        assert_that!(Resolved.transition(Resolve), is(equal_to(Err(ErrorKind::NotDisputed))));
+        assert_that!(Resolved.transition(Chargeback), is(equal_to(Err(ErrorKind::NotDisputed))));
+    }
+
+    #[test]
+    fn test_dispute_again() {
+        assert_that!(Resolved.transition(Dispute), is(equal_to(Ok(Disputed))));
+    }
+
+    #[test]
+    fn test_charged_back_is_final() {
+        assert_that!(ChargedBack.transition(Dispute), is(equal_to(Err(ErrorKind::AlreadyChargedBack))));
+        assert_that!(ChargedBack.transition(Resolve), is(equal_to(Err(ErrorKind::AlreadyChargedBack))));
+        assert_that!(ChargedBack.transition(Chargeback), is(equal_to(Err(ErrorKind::AlreadyChargedBack))));
+    }
+
+    #[test]
+    fn test_cannot_apply_again() {
+        assert_that!(Applied.transition(Deposit), is(equal_to(Err(ErrorKind::DuplicateTransaction))));
+        assert_that!(Resolved.transition(Withdrawal), is(equal_to(Err(ErrorKind::DuplicateTransaction))));
+    }
+}
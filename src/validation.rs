@@ -0,0 +1,80 @@
+use rust_decimal::Decimal;
+
+use crate::error::{EngineError, ErrorKind};
+use crate::Transaction;
+use crate::transaction::TransactionType;
+
+// Bounds on the amounts of deposits and withdrawals, checked before they reach the accounts.
+// Disputes, resolutions and chargebacks use the amount of the original transaction, so theirs is ignored.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AmountLimits {
+    pub max_deposit: Decimal,
+    pub max_withdrawal: Decimal,
+}
+
+impl Default for AmountLimits {
+    fn default() -> Self {
+        Self {
+            max_deposit: Decimal::from(1_000_000_000_000u64),
+            max_withdrawal: Decimal::from(1_000_000_000_000u64),
+        }
+    }
+}
+
+impl AmountLimits {
+    // A missing amount is reported by the engine, so it is not checked here
+    pub fn validate(&self, transaction: &Transaction) -> Result<(), EngineError> {
+        let max = match transaction.transaction_type {
+            TransactionType::Deposit => self.max_deposit,
+            TransactionType::Withdrawal => self.max_withdrawal,
+            _ => return Ok(()),
+        };
+
+        match transaction.amount {
+            Some(amount) if amount <= Decimal::ZERO => Err(EngineError::new(ErrorKind::NonPositiveAmount, transaction, Some(amount))),
+            Some(amount) if amount > max => Err(EngineError::new(ErrorKind::AmountTooLarge, transaction, Some(amount))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::*;
+    use rust_decimal_macros::dec;
+
+    use crate::transaction::TransactionType::*;
+
+    use super::*;
+
+    fn validate(transaction_type: TransactionType, amount: Option<Decimal>) -> Result<(), ErrorKind> {
+        let limits = AmountLimits { max_deposit: dec!(1000), max_withdrawal: dec!(100) };
+
+        limits.validate(&Transaction::new(transaction_type, 1, 1, amount)).map_err(|err| err.kind)
+    }
+
+    #[test]
+    fn test_valid_amounts() {
+        assert_that!(validate(Deposit, Some(dec!(1000))), is(equal_to(Ok(()))));
+        assert_that!(validate(Withdrawal, Some(dec!(0.0001))), is(equal_to(Ok(()))));
+        assert_that!(validate(Deposit, None), is(equal_to(Ok(()))));
+    }
+
+    #[test]
+    fn test_non_positive_amounts() {
+        assert_that!(validate(Deposit, Some(dec!(-100))), is(equal_to(Err(ErrorKind::NonPositiveAmount))));
+        assert_that!(validate(Withdrawal, Some(dec!(0))), is(equal_to(Err(ErrorKind::NonPositiveAmount))));
+    }
+
+    #[test]
+    fn test_too_large_amounts() {
+        assert_that!(validate(Deposit, Some(dec!(1000.0001))), is(equal_to(Err(ErrorKind::AmountTooLarge))));
+        assert_that!(validate(Withdrawal, Some(dec!(101))), is(equal_to(Err(ErrorKind::AmountTooLarge))));
+    }
+
+    #[test]
+    fn test_ignore_dispute_amounts() {
+        assert_that!(validate(Dispute, Some(dec!(0))), is(equal_to(Ok(()))));
+        assert_that!(validate(Chargeback, Some(dec!(-1))), is(equal_to(Ok(()))));
+    }
+}